@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS manifests (
+  id UUID PRIMARY KEY,
+  package TEXT NOT NULL,
+  version TEXT,
+  content_type TEXT NOT NULL,
+  body TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS manifests_package_idx ON manifests (package, created_at);
+
+CREATE TABLE IF NOT EXISTS manifest_orders (
+  manifest_id UUID NOT NULL REFERENCES manifests (id) ON DELETE CASCADE,
+  position INT NOT NULL,
+  item TEXT NOT NULL,
+  quantity DOUBLE PRECISION NOT NULL,
+  unit TEXT,
+  PRIMARY KEY (manifest_id, position)
+);
+
+CREATE INDEX IF NOT EXISTS manifest_orders_item_idx ON manifest_orders (item);
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS milk_tanks (
+  name TEXT PRIMARY KEY,
+  -- 升，精确到 0.001 ml
+  capacity NUMERIC(15, 6) NOT NULL CHECK (capacity > 0),
+  stock NUMERIC(15, 6) NOT NULL CHECK (stock >= 0 AND stock <= capacity),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO milk_tanks (name, capacity, stock) VALUES ('default', 1000, 1000)
+ON CONFLICT (name) DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS milk_ledger (
+  id BIGSERIAL PRIMARY KEY,
+  tank TEXT NOT NULL REFERENCES milk_tanks (name) ON DELETE CASCADE,
+  -- 客户端标识的 SHA-256 前缀，不保存原始的 API key、JWT subject 或 IP
+  client TEXT NOT NULL,
+  -- 取出为负，补充为正
+  change NUMERIC(15, 6) NOT NULL,
+  -- 变动后的库存
+  stock NUMERIC(15, 6) NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS milk_ledger_tank_idx ON milk_ledger (tank, created_at);
@@ -11,9 +11,11 @@ use service::day23;
 use service::day5;
 use service::day9;
 use service::day16;
+use service::rate_limit::{Algorithm, ClientIdentity, RateLimit, RateLimiter, SystemClock};
 use shuttle_actix_web::ShuttleActixWeb;
 use std::convert::Into;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 #[shuttle_runtime::main]
 async fn main(
@@ -21,15 +23,16 @@ async fn main(
     pool: sqlx::PgPool,
 ) -> ShuttleActixWeb<impl FnOnce(&mut ServiceConfig) + Send + Clone + 'static> {
 
-    let bucket = Arc::new(day9::TokenBucket::new(5));
-    let bucket_clone = bucket.clone();
+    // /9/milk 默认每个客户端 5 个令牌、每秒补充 1 个，RATE_LIMIT_ALGORITHM 可以换成其他算法
+    let algorithm = std::env::var("RATE_LIMIT_ALGORITHM")
+        .map_or(Algorithm::TokenBucket, |a| a.parse().expect("Invalid RATE_LIMIT_ALGORITHM"));
+    let bucket: Arc<dyn RateLimiter> = algorithm.build(5, Duration::from_secs(5), Arc::new(SystemClock));
     let board: Arc<RwLock<day12::Board>> = Default::default();
     let page_map: Arc<RwLock<HashMap<String, usize>>> = Default::default();
-
-    // 启动一个独立的任务来补充令牌
-    tokio::spawn(async move {
-        bucket_clone.replenish().await;
-    });
+    let policy = Arc::new(day5::Policy::load("assets/day5_policy.toml"));
+    let identity = Arc::new(ClientIdentity::from_env());
+    // /2 的批量和区间接口比较耗时，单独限流；在 app 工厂外创建，所有 worker 共用
+    let address_limit = RateLimit::new(Algorithm::TokenBucket, 100, Duration::from_secs(1));
 
     sqlx::migrate!()
         .run(&pool)
@@ -39,7 +42,7 @@ async fn main(
     let config = move |cfg: &mut ServiceConfig| {
         cfg.service(hello_world)
             .service(scope())
-            .service(day2::scope().wrap(Logger::default()))
+            .service(day2::scope().wrap(address_limit.clone()).wrap(Logger::default()))
             .service(day5::scope().wrap(Logger::default()))
             .service(day9::scope().wrap(Logger::default()))
             .service(day12::scope().wrap(Logger::default()))
@@ -51,6 +54,8 @@ async fn main(
         cfg.app_data(web::Data::new(board));
         cfg.app_data(web::Data::new(pool.clone()));
         cfg.app_data(web::Data::new(page_map));
+        cfg.app_data(web::Data::new(policy));
+        cfg.app_data(web::Data::new(identity));
 
         cfg.service(Files::new("/assets", "assets"));
     };
@@ -1,105 +1,234 @@
-use std::net::Ipv6Addr;
-use std::str::FromStr;
-use actix_web::{web};
-use actix_web::web::Query;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use actix_web::{web, HttpResponse};
+use actix_web::web::{Query, QueryConfig};
 use serde::Deserialize;
+use addr::{AddrError, Family, Reason};
+use cipher::{AddressCipher, CipherOp};
+use encoding::FormatParams;
+
+mod addr;
+mod batch;
+mod cidr;
+mod cipher;
+mod encoding;
+mod range;
 
 #[derive(Deserialize)]
 struct DestParams {
     from: String,
     key: String,
-}
-
-async fn dest(params: Query<DestParams>) -> String {
-    let from_nums: Vec<u8> = params
-        .from
-        .split(".")
-        .map(|s| s.parse::<u8>().unwrap())
-        .collect();
-    let key_nums: Vec<u8> = params
-        .key
-        .split(".")
-        .map(|s| s.parse::<u8>().unwrap())
-        .collect();
-
-    let r_nums: Vec<u8> = from_nums
-        .iter()
-        .zip(key_nums.iter())
-        .map(|(a, b)| a.wrapping_add(*b))
-        .collect();
-    r_nums
-        .iter()
-        .map(|n| n.to_string())
-        .collect::<Vec<String>>()
-        .join(".")
+    op: Option<CipherOp>,
 }
 
 #[derive(Deserialize)]
 struct ToParams {
     from: String,
     to: String,
+    op: Option<CipherOp>,
+}
+
+#[derive(Deserialize)]
+struct SourceParams {
+    to: String,
+    key: String,
+    op: Option<CipherOp>,
+}
+
+/// 两个地址族一致时返回 (a, b, 位宽)；IPv4-mapped 按 IPv4 处理
+fn operands(a: IpAddr, b: IpAddr) -> Option<(u128, u128, u32)> {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => Some((u32::from(a) as u128, u32::from(b) as u128, 32)),
+        (IpAddr::V6(a), IpAddr::V6(b)) => Some((u128::from(a), u128::from(b), 128)),
+        _ => None,
+    }
+}
+
+/// 结果沿用 from 的地址族与写法
+fn to_addr(value: u128, family: Family) -> IpAddr {
+    match family {
+        Family::Ipv4 => IpAddr::V4(Ipv4Addr::from(value as u32)),
+        Family::Ipv4Mapped => IpAddr::V6(Ipv4Addr::from(value as u32).to_ipv6_mapped()),
+        Family::Ipv6 => IpAddr::V6(Ipv6Addr::from(value)),
+    }
+}
+
+/// 地址的解析方式：自动识别地址族，或者只接受 IPv6
+type Parse = fn(&'static str, &str) -> Result<(IpAddr, Family), AddrError>;
+
+/// 解析两个地址并交给 cipher 运算，`f` 返回 None 时视为无解
+fn apply(
+    parse: Parse,
+    (a_param, a): (&'static str, &str),
+    (b_param, b): (&'static str, &str),
+    op: Option<CipherOp>,
+    f: impl Fn(&dyn AddressCipher, u128, u128, u32) -> Option<u128>,
+) -> Result<(IpAddr, Family), AddrError> {
+    let (a_addr, family) = parse(a_param, a)?;
+    let (b_addr, _) = parse(b_param, b)?;
+    let (x, y, bits) = operands(a_addr, b_addr)
+        .ok_or_else(|| AddrError::new(b_param, b, None, Reason::FamilyMismatch))?;
+
+    let cipher = op.unwrap_or_else(|| CipherOp::default_for(bits)).cipher();
+    f(cipher, x, y, bits)
+        .map(|v| (to_addr(v, family), family))
+        .ok_or_else(|| AddrError::new(b_param, b, None, Reason::NoKey))
 }
 
-async fn to(params: Query<ToParams>) -> String {
-    let from_nums: Vec<u8> = params
-        .from
-        .split(".")
-        .map(|s| s.parse::<u8>().unwrap())
-        .collect();
-    let to_nums: Vec<u8> = params
-        .to
-        .split(".")
-        .map(|s| s.parse::<u8>().unwrap())
-        .collect();
+fn dest_any(
+    parse: Parse,
+    from: &str,
+    key: &str,
+    op: Option<CipherOp>,
+) -> Result<(IpAddr, Family), AddrError> {
+    apply(parse, ("from", from), ("key", key), op, |c, a, k, bits| {
+        Some(c.encrypt(a, k, bits))
+    })
+}
 
-    let r_nums: Vec<u8> = from_nums
-        .iter()
-        .zip(to_nums.iter())
-        .map(|(a, b)| b.wrapping_sub(*a))
-        .collect();
-    r_nums
-        .iter()
-        .map(|n| n.to_string())
-        .collect::<Vec<String>>()
-        .join(".")
+fn key_any(
+    parse: Parse,
+    from: &str,
+    to: &str,
+    op: Option<CipherOp>,
+) -> Result<(IpAddr, Family), AddrError> {
+    if let Some(op) = op.filter(|op| !op.derives_key()) {
+        return Err(AddrError::new("op", op.as_str(), None, Reason::UnsupportedOp));
+    }
+    apply(parse, ("from", from), ("to", to), op, |c, a, b, bits| {
+        c.derive_key(a, b, bits)
+    })
 }
 
-fn xor_ipv6(a: String, b: String) -> String {
-    let a_addr = Ipv6Addr::from_str(a.as_str()).expect("'a' invalid ipv6 address");
-    let b_addr = Ipv6Addr::from_str(b.as_str()).expect("'b' invalid ipv6 address");
+fn source_any(
+    parse: Parse,
+    to: &str,
+    key: &str,
+    op: Option<CipherOp>,
+) -> Result<(IpAddr, Family), AddrError> {
+    apply(parse, ("to", to), ("key", key), op, |c, b, k, bits| {
+        Some(c.decrypt(b, k, bits))
+    })
+}
 
-    let a_arr: Vec<u16> = a_addr
-        .octets()
-        .chunks(2)
-        .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
-        .collect();
+async fn dest(
+    params: Query<DestParams>,
+    output: Query<FormatParams>,
+) -> Result<HttpResponse, AddrError> {
+    let (addr, family) = dest_any(addr::parse_ip, &params.from, &params.key, params.op)?;
+    Ok(encoding::render(addr, family, &output))
+}
 
-    let b_arr: Vec<u16> = b_addr
-        .octets()
-        .chunks(2)
-        .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
-        .collect();
+async fn to(
+    params: Query<ToParams>,
+    output: Query<FormatParams>,
+) -> Result<HttpResponse, AddrError> {
+    let (addr, family) = key_any(addr::parse_ip, &params.from, &params.to, params.op)?;
+    Ok(encoding::render(addr, family, &output))
+}
 
-    let xor_arr: Vec<u16> = a_arr.iter().zip(b_arr.iter()).map(|(a, b)| a ^ b).collect();
-    let xor_addr = Ipv6Addr::new(
-        xor_arr[0], xor_arr[1], xor_arr[2], xor_arr[3], xor_arr[4], xor_arr[5], xor_arr[6],
-        xor_arr[7],
-    );
-    xor_addr.to_string()
+/// 旧路由不识别地址族，所有地址都按 IPv6 异或
+async fn dest_v6(
+    params: Query<DestParams>,
+    output: Query<FormatParams>,
+) -> Result<HttpResponse, AddrError> {
+    let (addr, family) = dest_any(addr::parse_v6, &params.from, &params.key, params.op)?;
+    Ok(encoding::render(addr, family, &output))
 }
 
-async fn dest_v6(params: Query<DestParams>) -> String {
-    xor_ipv6(params.from.clone(), params.key.clone())
+async fn to_v6(
+    params: Query<ToParams>,
+    output: Query<FormatParams>,
+) -> Result<HttpResponse, AddrError> {
+    let (addr, family) = key_any(addr::parse_v6, &params.from, &params.to, params.op)?;
+    Ok(encoding::render(addr, family, &output))
 }
 
-async fn to_v6(params: Query<ToParams>) -> String {
-    xor_ipv6(params.from.clone(), params.to.clone())
+/// dest 的逆运算：由目标地址和 key 还原 from
+async fn source(
+    params: Query<SourceParams>,
+    output: Query<FormatParams>,
+) -> Result<HttpResponse, AddrError> {
+    let (addr, family) = source_any(addr::parse_ip, &params.to, &params.key, params.op)?;
+    Ok(encoding::render(addr, family, &output))
 }
 
 pub(crate) fn scope() -> actix_web::Scope {
     web::scope("/2")
+        .app_data(QueryConfig::default().error_handler(addr::query_error))
         .route("/dest", web::get().to(dest))
+        .route("/dest", web::post().to(batch::dest))
         .route("/key", web::get().to(to))
+        .route("/key", web::post().to(batch::key))
+        .route("/source", web::get().to(source))
+        .route("/range", web::get().to(range::range))
+        // 旧的 /v6 路由保留原来的 IPv6 语义
         .route("/v6/dest", web::get().to(dest_v6))
+        .route("/v6/dest", web::post().to(batch::dest_v6))
         .route("/v6/key", web::get().to(to_v6))
-}
\ No newline at end of file
+        .route("/v6/key", web::post().to(batch::key_v6))
+        .route("/cidr", web::get().to(cidr::info))
+        .route("/cidr/contains", web::get().to(cidr::contains))
+        .route("/cidr/split", web::get().to(cidr::split))
+        .route("/cidr/supernet", web::get().to(cidr::supernet))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text((addr, family): (IpAddr, Family)) -> (String, Family) {
+        (addr.to_string(), family)
+    }
+
+    #[test]
+    fn picks_operation_by_family() {
+        let v4 = dest_any(addr::parse_ip, "10.0.0.0", "1.2.3.255", None).unwrap();
+        assert_eq!(text(v4), ("11.2.3.255".to_string(), Family::Ipv4));
+        let v6 = dest_any(addr::parse_ip, "fe80::1", "5:6:7::3333", None).unwrap();
+        assert_eq!(text(v6), ("fe85:6:7::3332".to_string(), Family::Ipv6));
+        let key = key_any(addr::parse_ip, "10.0.0.0", "11.2.3.255", None).unwrap();
+        assert_eq!(text(key), ("1.2.3.255".to_string(), Family::Ipv4));
+    }
+
+    #[test]
+    fn mapped_addresses_on_unversioned_routes() {
+        let r = dest_any(addr::parse_ip, "::ffff:10.0.0.1", "0.0.1.0", None).unwrap();
+        assert_eq!(text(r), ("::ffff:10.0.1.1".to_string(), Family::Ipv4Mapped));
+        let err = dest_any(addr::parse_ip, "10.0.0.1", "::1", None).unwrap_err();
+        assert_eq!(err.to_string(), "`key`: address family differs from the other address");
+    }
+
+    #[test]
+    fn v6_routes_xor_mapped_addresses() {
+        let r = dest_any(addr::parse_v6, "::ffff:10.0.0.1", "::ffff:0.0.0.255", None).unwrap();
+        assert_eq!(text(r), ("::a00:fe".to_string(), Family::Ipv6));
+        let r = dest_any(addr::parse_v6, "::ffff:10.0.0.1", "::1", None).unwrap();
+        assert_eq!(text(r), ("::ffff:10.0.0.0".to_string(), Family::Ipv6));
+        let r = key_any(addr::parse_v6, "aaaa::aaaa", "5555:ffff:c:0:0:c:1234:5555", None).unwrap();
+        assert_eq!(text(r), ("ffff:ffff:c::c:1234:ffff".to_string(), Family::Ipv6));
+        assert!(dest_any(addr::parse_v6, "10.0.0.1", "::1", None).is_err());
+    }
+
+    #[test]
+    fn routes_round_trip_for_every_op() {
+        let ops = [CipherOp::Add, CipherOp::Sub, CipherOp::Xor, CipherOp::Rotate, CipherOp::Permute];
+        for op in ops {
+            for (from, key) in [("10.0.0.1", "1.2.3.255"), ("fe80::1", "5:6:7::3333")] {
+                let (dest, _) = dest_any(addr::parse_ip, from, key, Some(op)).unwrap();
+                let dest = dest.to_string();
+                let (source, _) = source_any(addr::parse_ip, &dest, key, Some(op)).unwrap();
+                assert_eq!(source.to_string(), from, "{:?}", op);
+                if op.derives_key() {
+                    let (derived, _) = key_any(addr::parse_ip, from, &dest, Some(op)).unwrap();
+                    let (again, _) = dest_any(addr::parse_ip, from, &derived.to_string(), Some(op)).unwrap();
+                    assert_eq!(again.to_string(), dest, "{:?}", op);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn key_rejects_permute() {
+        let err = key_any(addr::parse_ip, "10.0.0.1", "10.0.0.2", Some(CipherOp::Permute)).unwrap_err();
+        assert_eq!(err.to_string(), "`op`: the key cannot be derived for this op");
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use actix_web::error::QueryPayloadError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Family {
+    Ipv4,
+    Ipv6,
+    /// 形如 ::ffff:10.0.0.1，按 IPv4 运算，结果仍以该形式输出
+    Ipv4Mapped,
+}
+
+impl Family {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Family::Ipv4 => "ipv4",
+            Family::Ipv6 => "ipv6",
+            Family::Ipv4Mapped => "ipv4-mapped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Reason {
+    Malformed,
+    OutOfRange,
+    WrongOctetCount,
+    NonNumeric,
+    InvalidIpv6,
+    FamilyMismatch,
+    NoKey,
+    UnsupportedOp,
+    InvalidRange,
+    RangeSizeMismatch,
+    InvalidAddress,
+    InvalidCidr,
+    InvalidPrefix,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Reason::Malformed => "malformed request",
+            Reason::OutOfRange => "octet out of range 0-255",
+            Reason::WrongOctetCount => "expected 4 octets",
+            Reason::NonNumeric => "octet is not a decimal number",
+            Reason::InvalidIpv6 => "not a valid IPv6 address",
+            Reason::FamilyMismatch => "address family differs from the other address",
+            Reason::NoKey => "no key maps the addresses onto each other with the chosen op",
+            Reason::UnsupportedOp => "the key cannot be derived for this op",
+            Reason::InvalidRange => "not a CIDR block, `first-last` range or address",
+            Reason::RangeSizeMismatch => "ranges differ in size",
+            Reason::InvalidAddress => "not a valid IP address",
+            Reason::InvalidCidr => "not a CIDR block such as 10.0.0.0/8",
+            Reason::InvalidPrefix => "prefix length is outside the allowed range",
+        })
+    }
+}
+
+/// 400 响应体，格式参照 RFC 9457 problem document
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct AddrError {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    param: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    /// 出错的八位组下标，从 0 开始
+    #[serde(skip_serializing_if = "Option::is_none")]
+    octet: Option<usize>,
+    reason: Reason,
+}
+
+impl AddrError {
+    pub(crate) fn new(param: &'static str, value: &str, octet: Option<usize>, reason: Reason) -> Self {
+        let detail = match octet {
+            Some(i) => format!("`{}` octet {}: {}", param, i, reason),
+            None => format!("`{}`: {}", param, reason),
+        };
+        Self {
+            kind: "about:blank",
+            title: "Invalid address",
+            status: StatusCode::BAD_REQUEST.as_u16(),
+            detail,
+            param: Some(param),
+            value: Some(value.to_string()),
+            octet,
+            reason,
+        }
+    }
+
+    pub(crate) fn malformed(title: &'static str, detail: String) -> Self {
+        Self {
+            kind: "about:blank",
+            title,
+            status: StatusCode::BAD_REQUEST.as_u16(),
+            detail,
+            param: None,
+            value: None,
+            octet: None,
+            reason: Reason::Malformed,
+        }
+    }
+}
+
+impl fmt::Display for AddrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+impl ResponseError for AddrError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .content_type("application/problem+json")
+            .json(self)
+    }
+}
+
+pub(crate) fn parse_ipv4(param: &'static str, s: &str) -> Result<Ipv4Addr, AddrError> {
+    let parts: Vec<&str> = s.split('.').collect();
+    if parts.len() != 4 {
+        return Err(AddrError::new(param, s, None, Reason::WrongOctetCount));
+    }
+
+    let mut octets = [0u8; 4];
+    for (i, part) in parts.iter().enumerate() {
+        // u8::from_str 会接受 "+1"，这里只认纯数字
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(AddrError::new(param, s, Some(i), Reason::NonNumeric));
+        }
+        octets[i] = part
+            .parse::<u8>()
+            .map_err(|_| AddrError::new(param, s, Some(i), Reason::OutOfRange))?;
+    }
+    Ok(Ipv4Addr::from(octets))
+}
+
+pub(crate) fn parse_ipv6(param: &'static str, s: &str) -> Result<Ipv6Addr, AddrError> {
+    Ipv6Addr::from_str(s).map_err(|_| AddrError::new(param, s, None, Reason::InvalidIpv6))
+}
+
+/// 按写法自动识别地址族；IPv4-mapped 地址解析为其中的 IPv4 地址
+pub(crate) fn parse_ip(param: &'static str, s: &str) -> Result<(IpAddr, Family), AddrError> {
+    if !s.contains(':') {
+        return parse_ipv4(param, s).map(|a| (IpAddr::V4(a), Family::Ipv4));
+    }
+
+    let v6 = parse_ipv6(param, s)?;
+    Ok(match v6.to_ipv4_mapped() {
+        Some(v4) => (IpAddr::V4(v4), Family::Ipv4Mapped),
+        None => (IpAddr::V6(v6), Family::Ipv6),
+    })
+}
+
+/// 旧的 /v6 路由只接受 IPv6，IPv4-mapped 地址也按 128 位处理
+pub(crate) fn parse_v6(param: &'static str, s: &str) -> Result<(IpAddr, Family), AddrError> {
+    parse_ipv6(param, s).map(|a| (IpAddr::V6(a), Family::Ipv6))
+}
+
+/// 缺少参数时同样返回 problem document
+pub(crate) fn query_error(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    AddrError::malformed("Invalid query", err.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_family() {
+        assert_eq!(
+            parse_ip("from", "10.0.0.1").unwrap(),
+            (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), Family::Ipv4)
+        );
+        assert_eq!(
+            parse_ip("from", "::ffff:10.0.0.1").unwrap(),
+            (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), Family::Ipv4Mapped)
+        );
+        assert_eq!(parse_ip("from", "fe80::1").unwrap().1, Family::Ipv6);
+    }
+
+    #[test]
+    fn v6_keeps_mapped_addresses() {
+        let (addr, family) = parse_v6("from", "::ffff:10.0.0.1").unwrap();
+        assert_eq!(addr, "::ffff:10.0.0.1".parse::<IpAddr>().unwrap());
+        assert!(addr.is_ipv6());
+        assert_eq!(family, Family::Ipv6);
+        assert_eq!(parse_v6("from", "10.0.0.1").unwrap_err().reason, Reason::InvalidIpv6);
+    }
+
+    #[test]
+    fn reports_bad_octets() {
+        let err = parse_ipv4("from", "10.0.300.1").unwrap_err();
+        assert_eq!((err.octet, err.reason), (Some(2), Reason::OutOfRange));
+        let err = parse_ipv4("key", "10.0.+1.1").unwrap_err();
+        assert_eq!((err.octet, err.reason), (Some(2), Reason::NonNumeric));
+        let err = parse_ipv4("key", "10.0.1").unwrap_err();
+        assert_eq!((err.param, err.reason), (Some("key"), Reason::WrongOctetCount));
+    }
+}
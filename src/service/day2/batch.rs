@@ -0,0 +1,160 @@
+use std::net::IpAddr;
+use actix_web::web::Bytes;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use futures::stream::{self, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use shuttle_runtime::__internals::serde_json;
+use super::addr::{parse_ip, parse_v6, AddrError, Family};
+use super::{DestParams, ToParams};
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BatchItem {
+    Ok {
+        index: usize,
+        result: IpAddr,
+        family: Family,
+    },
+    Err { index: usize, error: AddrError },
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    NdJson,
+}
+
+/// JSON 数组整体解析失败时直接 400；单条记录的错误放在对应位置返回
+fn parse_items<T: DeserializeOwned>(
+    body: &str,
+    format: Format,
+) -> Result<Vec<Result<T, AddrError>>, AddrError> {
+    let item = |r: Result<T, serde_json::Error>| {
+        r.map_err(|e| AddrError::malformed("Invalid item", e.to_string()))
+    };
+
+    match format {
+        Format::Json => serde_json::from_str::<Vec<serde_json::Value>>(body)
+            .map(|values| {
+                values
+                    .into_iter()
+                    .map(|v| item(serde_json::from_value(v)))
+                    .collect()
+            })
+            .map_err(|e| AddrError::malformed("Invalid batch", e.to_string())),
+        Format::NdJson => Ok(body
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| item(serde_json::from_str(line)))
+            .collect()),
+    }
+}
+
+fn run<T, F>(body: String, req: HttpRequest, op: F) -> HttpResponse
+where
+    T: DeserializeOwned + 'static,
+    F: Fn(T) -> Result<(IpAddr, Family), AddrError> + 'static,
+{
+    let format = match req.content_type() {
+        "application/json" => Format::Json,
+        "application/x-ndjson" => Format::NdJson,
+        _ => return HttpResponse::UnsupportedMediaType().finish(),
+    };
+    let items = match parse_items::<T>(&body, format) {
+        Ok(items) => items,
+        Err(e) => return HttpResponse::from_error(e),
+    };
+
+    let results = stream::iter(items.into_iter().enumerate()).map(move |(index, item)| {
+        let item = match item.and_then(&op) {
+            Ok((result, family)) => BatchItem::Ok {
+                index,
+                result,
+                family,
+            },
+            Err(error) => BatchItem::Err { index, error },
+        };
+        let line = serde_json::to_string(&item).unwrap_or_default();
+        let chunk = match (format, index) {
+            (Format::Json, 0) => line,
+            (Format::Json, _) => format!(",{}", line),
+            (Format::NdJson, _) => format!("{}\n", line),
+        };
+        Ok::<_, actix_web::Error>(Bytes::from(chunk))
+    });
+
+    match format {
+        Format::Json => HttpResponse::Ok().content_type("application/json").streaming(
+            stream::once(async { Ok(Bytes::from_static(b"[")) })
+                .chain(results)
+                .chain(stream::once(async { Ok(Bytes::from_static(b"]")) })),
+        ),
+        Format::NdJson => HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .streaming(results),
+    }
+}
+
+pub(crate) async fn dest(body: String, req: HttpRequest) -> HttpResponse {
+    run(body, req, |p: DestParams| super::dest_any(parse_ip, &p.from, &p.key, p.op))
+}
+
+pub(crate) async fn key(body: String, req: HttpRequest) -> HttpResponse {
+    run(body, req, |p: ToParams| super::key_any(parse_ip, &p.from, &p.to, p.op))
+}
+
+pub(crate) async fn dest_v6(body: String, req: HttpRequest) -> HttpResponse {
+    run(body, req, |p: DestParams| super::dest_any(parse_v6, &p.from, &p.key, p.op))
+}
+
+pub(crate) async fn key_v6(body: String, req: HttpRequest) -> HttpResponse {
+    run(body, req, |p: ToParams| super::key_any(parse_v6, &p.from, &p.to, p.op))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::to_bytes;
+    use actix_web::test::TestRequest;
+    use super::*;
+
+    async fn call(content_type: &str, body: &str) -> (u16, String) {
+        let req = TestRequest::default()
+            .insert_header(("Content-Type", content_type))
+            .to_http_request();
+        let resp = dest(body.to_string(), req).await;
+        let status = resp.status().as_u16();
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn streams_a_json_array() {
+        let body = r#"[{"from": "10.0.0.0", "key": "1.2.3.255"}, {"from": "10.0.0.0"}]"#;
+        let (status, body) = call("application/json", body).await;
+        assert_eq!(status, 200);
+        let items: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(items[0]["result"], "11.2.3.255");
+        assert_eq!(items[0]["family"], "ipv4");
+        assert_eq!(items[1]["index"], 1);
+        assert_eq!(items[1]["error"]["title"], "Invalid item");
+    }
+
+    #[actix_web::test]
+    async fn streams_ndjson_lines() {
+        let body = "{\"from\": \"fe80::1\", \"key\": \"5:6:7::3333\"}\n\n{\"from\": \"1.2.3\", \"key\": \"1.1.1.1\"}\n";
+        let (status, body) = call("application/x-ndjson", body).await;
+        assert_eq!(status, 200);
+        let items: Vec<serde_json::Value> = body.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["result"], "fe85:6:7::3332");
+        assert_eq!(items[1]["error"]["reason"], "wrong_octet_count");
+    }
+
+    #[actix_web::test]
+    async fn rejects_bad_batches() {
+        assert_eq!(call("application/json", "{}").await.0, 400);
+        assert_eq!(call("text/plain", "[]").await.0, 415);
+        assert_eq!(call("application/json", "[]").await, (200, "[]".to_string()));
+    }
+}
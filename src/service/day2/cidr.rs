@@ -0,0 +1,381 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use actix_web::web::Query;
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use super::addr::{AddrError, Reason};
+
+/// 单次 split 最多返回的子网数量
+const MAX_SUBNETS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s
+            .split_once('/')
+            .ok_or_else(|| format!("Missing prefix length: {}", s))?;
+        let addr = IpAddr::from_str(addr).map_err(|_| format!("Invalid address: {}", addr))?;
+        let prefix = prefix
+            .parse::<u8>()
+            .map_err(|_| format!("Invalid prefix length: {}", prefix))?;
+        Cidr::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network(), self.prefix)
+    }
+}
+
+impl Serialize for Cidr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl Cidr {
+    pub(crate) fn new(addr: IpAddr, prefix: u8) -> Result<Self, String> {
+        let cidr = Self { addr, prefix };
+        if prefix > cidr.bits() {
+            return Err(format!(
+                "Prefix length {} exceeds {} bits",
+                prefix,
+                cidr.bits()
+            ));
+        }
+        Ok(cidr)
+    }
+
+    fn bits(&self) -> u8 {
+        match self.addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    fn host_bits(&self) -> u32 {
+        (self.bits() - self.prefix) as u32
+    }
+
+    fn value(&self) -> u128 {
+        to_u128(self.addr)
+    }
+
+    fn addr_of(&self, value: u128) -> IpAddr {
+        match self.addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(value as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(value)),
+        }
+    }
+
+    /// 主机位掩码，例如 /22 的 IPv4 为 0.0.3.255
+    fn host_mask(&self) -> u128 {
+        u128::MAX
+            .checked_shr(128 - self.host_bits())
+            .unwrap_or(0)
+    }
+
+    fn netmask(&self) -> IpAddr {
+        let all = u128::MAX >> (128 - self.bits() as u32);
+        self.addr_of(all & !self.host_mask())
+    }
+
+    fn network_value(&self) -> u128 {
+        self.value() & !self.host_mask()
+    }
+
+    pub(crate) fn network(&self) -> IpAddr {
+        self.addr_of(self.network_value())
+    }
+
+    /// 范围内的最后一个地址，IPv4 即广播地址
+    pub(crate) fn last(&self) -> IpAddr {
+        self.addr_of(self.network_value() | self.host_mask())
+    }
+
+    /// 可用主机范围；IPv4 的 /31 和 /32 按 RFC 3021 不保留网络与广播地址
+    fn host_range(&self) -> (IpAddr, IpAddr) {
+        let network = self.network_value();
+        let last = network | self.host_mask();
+        match self.addr {
+            IpAddr::V4(_) if self.host_bits() >= 2 => {
+                (self.addr_of(network + 1), self.addr_of(last - 1))
+            }
+            _ => (self.addr_of(network), self.addr_of(last)),
+        }
+    }
+
+    fn size(&self) -> Option<u128> {
+        1u128.checked_shl(self.host_bits())
+    }
+
+    fn host_count(&self) -> String {
+        match (self.addr, self.size()) {
+            (IpAddr::V4(_), Some(size)) if self.host_bits() >= 2 => (size - 2).to_string(),
+            (_, Some(size)) => size.to_string(),
+            // IPv6 的 ::/0 共 2^128 个地址，超出 u128
+            (_, None) => "340282366920938463463374607431768211456".to_string(),
+        }
+    }
+
+    pub(crate) fn contains(&self, addr: IpAddr) -> bool {
+        addr.is_ipv4() == self.addr.is_ipv4()
+            && to_u128(addr) & !self.host_mask() == self.network_value()
+    }
+
+    fn supernet(&self, prefix: u8) -> Result<Cidr, String> {
+        if prefix > self.prefix {
+            return Err(format!(
+                "Supernet prefix {} is longer than /{}",
+                prefix, self.prefix
+            ));
+        }
+        Cidr::new(self.network(), prefix)
+    }
+
+    fn subnets(&self, prefix: u8) -> Result<impl Iterator<Item = Cidr> + '_, String> {
+        if prefix < self.prefix || prefix > self.bits() {
+            return Err(format!(
+                "Subnet prefix {} must be between {} and {}",
+                prefix,
+                self.prefix,
+                self.bits()
+            ));
+        }
+        let step_bits = (self.bits() - prefix) as u32;
+        let count = 1u128.checked_shl((prefix - self.prefix) as u32);
+        let network = self.network_value();
+        Ok((0..count.unwrap_or(u128::MAX)).map(move |i| Cidr {
+            addr: self.addr_of(network | i.checked_shl(step_bits).unwrap_or(0)),
+            prefix,
+        }))
+    }
+}
+
+pub(crate) fn to_u128(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(v4) => u32::from(v4) as u128,
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+#[derive(Serialize)]
+struct CidrInfo {
+    cidr: Cidr,
+    family: &'static str,
+    prefix: u8,
+    network: IpAddr,
+    netmask: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    broadcast: Option<IpAddr>,
+    first_host: IpAddr,
+    last_host: IpAddr,
+    host_count: String,
+}
+
+impl From<Cidr> for CidrInfo {
+    fn from(cidr: Cidr) -> Self {
+        let (first_host, last_host) = cidr.host_range();
+        Self {
+            cidr,
+            family: if cidr.addr.is_ipv4() { "ipv4" } else { "ipv6" },
+            prefix: cidr.prefix,
+            network: cidr.network(),
+            netmask: cidr.netmask(),
+            broadcast: cidr.addr.is_ipv4().then(|| cidr.last()),
+            first_host,
+            last_host,
+            host_count: cidr.host_count(),
+        }
+    }
+}
+
+/// 查询参数中的 CIDR，错误与 /2 其他路由一样返回 problem document
+fn parse_param(s: &str) -> Result<Cidr, AddrError> {
+    Cidr::from_str(s).map_err(|_| AddrError::new("cidr", s, None, Reason::InvalidCidr))
+}
+
+fn invalid_prefix(prefix: u8) -> AddrError {
+    AddrError::new("prefix", &prefix.to_string(), None, Reason::InvalidPrefix)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CidrParams {
+    cidr: String,
+}
+
+pub(crate) async fn info(params: Query<CidrParams>) -> Result<HttpResponse, AddrError> {
+    let cidr = parse_param(&params.cidr)?;
+    Ok(HttpResponse::Ok().json(CidrInfo::from(cidr)))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ContainsParams {
+    cidr: String,
+    ip: String,
+}
+
+#[derive(Serialize)]
+struct ContainsResp {
+    cidr: Cidr,
+    ip: IpAddr,
+    contains: bool,
+}
+
+pub(crate) async fn contains(params: Query<ContainsParams>) -> Result<HttpResponse, AddrError> {
+    let cidr = parse_param(&params.cidr)?;
+    let ip = IpAddr::from_str(&params.ip)
+        .map_err(|_| AddrError::new("ip", &params.ip, None, Reason::InvalidAddress))?;
+
+    Ok(HttpResponse::Ok().json(ContainsResp {
+        cidr,
+        ip,
+        contains: cidr.contains(ip),
+    }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PrefixParams {
+    cidr: String,
+    prefix: u8,
+}
+
+#[derive(Serialize)]
+struct SplitResp {
+    cidr: Cidr,
+    prefix: u8,
+    truncated: bool,
+    subnets: Vec<Cidr>,
+}
+
+pub(crate) async fn split(params: Query<PrefixParams>) -> Result<HttpResponse, AddrError> {
+    let cidr = parse_param(&params.cidr)?;
+    let mut subnets = cidr.subnets(params.prefix).map_err(|_| invalid_prefix(params.prefix))?;
+
+    let head: Vec<Cidr> = subnets.by_ref().take(MAX_SUBNETS).collect();
+    Ok(HttpResponse::Ok().json(SplitResp {
+        cidr,
+        prefix: params.prefix,
+        truncated: subnets.next().is_some(),
+        subnets: head,
+    }))
+}
+
+pub(crate) async fn supernet(params: Query<PrefixParams>) -> Result<HttpResponse, AddrError> {
+    let cidr = parse_param(&params.cidr)?;
+    let supernet = cidr.supernet(params.prefix).map_err(|_| invalid_prefix(params.prefix))?;
+    Ok(HttpResponse::Ok().json(CidrInfo::from(supernet)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::to_bytes;
+    use actix_web::ResponseError;
+    use shuttle_runtime::__internals::serde_json::{self, Value as JsonValue};
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_normalizes() {
+        assert_eq!(cidr("10.1.2.3/22").to_string(), "10.1.0.0/22");
+        assert_eq!(cidr("2001:db8::1/32").to_string(), "2001:db8::/32");
+        assert_eq!(Cidr::from_str("10.0.0.0").unwrap_err(), "Missing prefix length: 10.0.0.0");
+        assert_eq!(Cidr::from_str("10.0.0/8").unwrap_err(), "Invalid address: 10.0.0");
+        assert_eq!(Cidr::from_str("10.0.0.0/x").unwrap_err(), "Invalid prefix length: x");
+        assert_eq!(Cidr::from_str("10.0.0.0/33").unwrap_err(), "Prefix length 33 exceeds 32 bits");
+    }
+
+    #[test]
+    fn describes_ipv4_blocks() {
+        let block = cidr("192.168.5.9/22");
+        assert_eq!(block.netmask(), ip("255.255.252.0"));
+        assert_eq!(block.last(), ip("192.168.7.255"));
+        assert_eq!(block.host_range(), (ip("192.168.4.1"), ip("192.168.7.254")));
+        assert_eq!(block.host_count(), "1022");
+        // RFC 3021
+        assert_eq!(cidr("10.0.0.0/31").host_range(), (ip("10.0.0.0"), ip("10.0.0.1")));
+        assert_eq!(cidr("10.0.0.0/31").host_count(), "2");
+        assert_eq!(cidr("10.0.0.7/32").host_count(), "1");
+        assert_eq!(cidr("0.0.0.0/0").netmask(), ip("0.0.0.0"));
+    }
+
+    #[test]
+    fn describes_ipv6_blocks() {
+        assert_eq!(cidr("::/0").host_count(), "340282366920938463463374607431768211456");
+        assert_eq!(cidr("::/0").last(), ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"));
+        assert_eq!(cidr("2001:db8::/126").host_range(), (ip("2001:db8::"), ip("2001:db8::3")));
+        assert_eq!(cidr("2001:db8::/64").netmask(), ip("ffff:ffff:ffff:ffff::"));
+    }
+
+    #[test]
+    fn checks_membership() {
+        let block = cidr("10.0.0.0/8");
+        assert!(block.contains(ip("10.255.0.1")));
+        assert!(!block.contains(ip("11.0.0.0")));
+        assert!(!block.contains(ip("::ffff:10.0.0.1")));
+        assert!(cidr("::/0").contains(ip("::1")));
+    }
+
+    #[test]
+    fn splits_and_merges() {
+        let block = cidr("10.0.0.0/24");
+        let subnets: Vec<String> = block.subnets(26).unwrap().map(|c| c.to_string()).collect();
+        assert_eq!(subnets, ["10.0.0.0/26", "10.0.0.64/26", "10.0.0.128/26", "10.0.0.192/26"]);
+        assert!(block.subnets(23).is_err());
+        assert!(block.subnets(33).is_err());
+        assert_eq!(cidr("::/0").subnets(128).unwrap().take(2).last(), Some(cidr("::1/128")));
+
+        assert_eq!(block.supernet(16).unwrap(), cidr("10.0.0.0/16"));
+        assert_eq!(
+            block.supernet(25).unwrap_err(),
+            "Supernet prefix 25 is longer than /24"
+        );
+    }
+
+    async fn problem(resp: Result<HttpResponse, AddrError>) -> (String, JsonValue) {
+        let resp = resp.unwrap_or_else(|e| e.error_response());
+        assert_eq!(resp.status(), 400);
+        let content_type = resp.headers().get("Content-Type").unwrap().to_str().unwrap().to_string();
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        (content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn handlers_return_problem_documents() {
+        let (content_type, body) = problem(info(Query::from_query("cidr=10.0.0.0/33").unwrap()).await).await;
+        assert_eq!(content_type, "application/problem+json");
+        assert_eq!((&body["param"], &body["reason"]), (&"cidr".into(), &"invalid_cidr".into()));
+
+        let query = Query::from_query("cidr=10.0.0.0/8&ip=10.0.0").unwrap();
+        let (content_type, body) = problem(contains(query).await).await;
+        assert_eq!(content_type, "application/problem+json");
+        assert_eq!((&body["param"], &body["reason"]), (&"ip".into(), &"invalid_address".into()));
+
+        let (content_type, body) = problem(split(Query::from_query("cidr=10.0.0.0/24&prefix=23").unwrap()).await).await;
+        assert_eq!(content_type, "application/problem+json");
+        assert_eq!((&body["param"], &body["reason"]), (&"prefix".into(), &"invalid_prefix".into()));
+
+        let query = Query::from_query("cidr=10.0.0.0/24&prefix=25").unwrap();
+        let (content_type, body) = problem(supernet(query).await).await;
+        assert_eq!(content_type, "application/problem+json");
+        assert_eq!(body["detail"], "`prefix`: prefix length is outside the allowed range");
+    }
+}
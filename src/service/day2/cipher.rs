@@ -0,0 +1,256 @@
+use serde::Deserialize;
+
+/// 地址统一按 u128 运算，`bits` 为 32（IPv4）或 128（IPv6）
+pub(crate) trait AddressCipher {
+    /// /2/dest：用 key 把 addr 映射到目标地址
+    fn encrypt(&self, addr: u128, key: u128, bits: u32) -> u128;
+
+    /// encrypt 的逆运算，decrypt(encrypt(a, k), k) == a
+    fn decrypt(&self, addr: u128, key: u128, bits: u32) -> u128;
+
+    /// /2/key：找出满足 encrypt(from, key) == to 的 key，无解时返回 None
+    fn derive_key(&self, from: u128, to: u128, bits: u32) -> Option<u128>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CipherOp {
+    Add,
+    Sub,
+    Xor,
+    Rotate,
+    Permute,
+}
+
+impl CipherOp {
+    /// 未指定 op 时沿用原有行为：IPv4 逐字节相加，IPv6 异或
+    pub(crate) fn default_for(bits: u32) -> Self {
+        if bits == 128 {
+            CipherOp::Xor
+        } else {
+            CipherOp::Add
+        }
+    }
+
+    /// permute 无法由地址对反推 key，/2/key 不支持
+    pub(crate) fn derives_key(&self) -> bool {
+        *self != CipherOp::Permute
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            CipherOp::Add => "add",
+            CipherOp::Sub => "sub",
+            CipherOp::Xor => "xor",
+            CipherOp::Rotate => "rotate",
+            CipherOp::Permute => "permute",
+        }
+    }
+
+    pub(crate) fn cipher(&self) -> &'static dyn AddressCipher {
+        match self {
+            CipherOp::Add => &Add,
+            CipherOp::Sub => &Sub,
+            CipherOp::Xor => &Xor,
+            CipherOp::Rotate => &Rotate,
+            CipherOp::Permute => &Permute,
+        }
+    }
+}
+
+fn mask(bits: u32) -> u128 {
+    u128::MAX >> (128 - bits)
+}
+
+/// 逐字节运算，与原来 IPv4 按八位组 wrapping 加减的行为一致
+fn bytewise(a: u128, b: u128, bits: u32, f: fn(u8, u8) -> u8) -> u128 {
+    let mut out = a.to_be_bytes();
+    for (x, y) in out.iter_mut().zip(b.to_be_bytes()) {
+        *x = f(*x, y);
+    }
+    u128::from_be_bytes(out) & mask(bits)
+}
+
+struct Add;
+
+impl AddressCipher for Add {
+    fn encrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        bytewise(addr, key, bits, u8::wrapping_add)
+    }
+
+    fn decrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        bytewise(addr, key, bits, u8::wrapping_sub)
+    }
+
+    fn derive_key(&self, from: u128, to: u128, bits: u32) -> Option<u128> {
+        Some(bytewise(to, from, bits, u8::wrapping_sub))
+    }
+}
+
+struct Sub;
+
+impl AddressCipher for Sub {
+    fn encrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        bytewise(addr, key, bits, u8::wrapping_sub)
+    }
+
+    fn decrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        bytewise(addr, key, bits, u8::wrapping_add)
+    }
+
+    fn derive_key(&self, from: u128, to: u128, bits: u32) -> Option<u128> {
+        Some(bytewise(from, to, bits, u8::wrapping_sub))
+    }
+}
+
+struct Xor;
+
+impl AddressCipher for Xor {
+    fn encrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        (addr ^ key) & mask(bits)
+    }
+
+    fn decrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        self.encrypt(addr, key, bits)
+    }
+
+    fn derive_key(&self, from: u128, to: u128, bits: u32) -> Option<u128> {
+        Some(self.encrypt(from, to, bits))
+    }
+}
+
+/// 循环左移 key % bits 位
+struct Rotate;
+
+impl Rotate {
+    fn rotl(v: u128, n: u32, bits: u32) -> u128 {
+        let n = n % bits;
+        if n == 0 {
+            return v;
+        }
+        ((v << n) | (v >> (bits - n))) & mask(bits)
+    }
+}
+
+impl AddressCipher for Rotate {
+    fn encrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        Self::rotl(addr, (key % bits as u128) as u32, bits)
+    }
+
+    fn decrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        let n = (key % bits as u128) as u32;
+        Self::rotl(addr, bits - n, bits)
+    }
+
+    /// 返回最小的位移量
+    fn derive_key(&self, from: u128, to: u128, bits: u32) -> Option<u128> {
+        (0..bits)
+            .find(|n| Self::rotl(from, *n, bits) == to)
+            .map(u128::from)
+    }
+}
+
+/// 以 key 为轮密钥的 Feistel 网络，输出与输入位宽相同
+struct Permute;
+
+impl Permute {
+    const ROUNDS: u32 = 8;
+
+    fn round(half: u128, key: u128, round: u32) -> u128 {
+        // splitmix64 的混合函数
+        let mut x = (half as u64)
+            ^ (key as u64).rotate_left(round * 8)
+            ^ ((key >> 64) as u64)
+            ^ (round as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (x ^ (x >> 31)) as u128
+    }
+}
+
+impl AddressCipher for Permute {
+    fn encrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        let half = bits / 2;
+        let (mut l, mut r) = (addr >> half, addr & mask(half));
+        for i in 0..Self::ROUNDS {
+            (l, r) = (r, l ^ (Self::round(r, key, i) & mask(half)));
+        }
+        (l << half) | r
+    }
+
+    fn decrypt(&self, addr: u128, key: u128, bits: u32) -> u128 {
+        let half = bits / 2;
+        let (mut l, mut r) = (addr >> half, addr & mask(half));
+        for i in (0..Self::ROUNDS).rev() {
+            (l, r) = (r ^ (Self::round(l, key, i) & mask(half)), l);
+        }
+        (l << half) | r
+    }
+
+    /// 不能从明文、密文对反推轮密钥，调用方应先用 derives_key 排除
+    fn derive_key(&self, _from: u128, _to: u128, _bits: u32) -> Option<u128> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPS: [CipherOp; 5] = [
+        CipherOp::Add,
+        CipherOp::Sub,
+        CipherOp::Xor,
+        CipherOp::Rotate,
+        CipherOp::Permute,
+    ];
+
+    const SAMPLES: [(u128, u128); 4] = [
+        (0, 0),
+        (0x0a00_0001, 0x0102_03ff),
+        (0xffff_ffff, 0x8000_0001),
+        (0xfe80_0000_0000_0000_0000_0000_0000_0001, 0x0005_0006_0007_0000_0000_0000_0000_3333),
+    ];
+
+    #[test]
+    fn decrypt_inverts_encrypt() {
+        for op in OPS {
+            let cipher = op.cipher();
+            for bits in [32, 128] {
+                for (addr, key) in SAMPLES {
+                    let addr = addr & mask(bits);
+                    let dest = cipher.encrypt(addr, key, bits);
+                    assert!(dest <= mask(bits), "{:?} leaves {} bits", op, bits);
+                    assert_eq!(cipher.decrypt(dest, key, bits), addr, "{:?} with {} bits", op, bits);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn derived_key_maps_from_onto_to() {
+        for op in OPS.into_iter().filter(CipherOp::derives_key) {
+            let cipher = op.cipher();
+            for bits in [32, 128] {
+                for (addr, key) in SAMPLES {
+                    let addr = addr & mask(bits);
+                    let dest = cipher.encrypt(addr, key, bits);
+                    let derived = cipher.derive_key(addr, dest, bits).expect("key exists");
+                    assert_eq!(cipher.encrypt(addr, derived, bits), dest, "{:?} with {} bits", op, bits);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotate_has_no_key_for_unrelated_addresses() {
+        assert_eq!(Rotate.derive_key(0b1, 0b11, 32), None);
+    }
+
+    #[test]
+    fn defaults_match_the_original_routes() {
+        assert_eq!(CipherOp::default_for(32), CipherOp::Add);
+        assert_eq!(CipherOp::default_for(128), CipherOp::Xor);
+        assert!(!CipherOp::Permute.derives_key());
+    }
+}
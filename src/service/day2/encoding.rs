@@ -0,0 +1,160 @@
+use std::net::IpAddr;
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use super::addr::Family;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputFormat {
+    /// 点分十进制或压缩后的 IPv6 写法
+    #[default]
+    Text,
+    Expanded,
+    Hex,
+    Int,
+    Binary,
+    Ptr,
+    Json,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FormatParams {
+    #[serde(default)]
+    format: OutputFormat,
+}
+
+/// format=json 时一次返回全部编码；int 用字符串避免 u128 超出 JSON 数字精度
+#[derive(Serialize)]
+struct Encodings {
+    address: IpAddr,
+    family: Family,
+    expanded: String,
+    hex: String,
+    int: String,
+    binary: String,
+    ptr: String,
+}
+
+fn value(addr: IpAddr) -> (u128, usize) {
+    match addr {
+        IpAddr::V4(a) => (u32::from(a) as u128, 32),
+        IpAddr::V6(a) => (u128::from(a), 128),
+    }
+}
+
+/// IPv6 补全为 8 组 4 位十六进制；IPv4 没有省略写法，原样返回
+fn expanded(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(a) => a.to_string(),
+        IpAddr::V6(a) => a
+            .segments()
+            .iter()
+            .map(|s| format!("{:04x}", s))
+            .collect::<Vec<_>>()
+            .join(":"),
+    }
+}
+
+fn hex(addr: IpAddr) -> String {
+    let (v, bits) = value(addr);
+    format!("{:0width$x}", v, width = bits / 4)
+}
+
+fn binary(addr: IpAddr) -> String {
+    let (v, bits) = value(addr);
+    format!("{:0width$b}", v, width = bits)
+}
+
+/// 反向解析域名，不带末尾的点
+fn ptr(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(a) => {
+            let octets = a.octets();
+            format!(
+                "{}.{}.{}.{}.in-addr.arpa",
+                octets[3], octets[2], octets[1], octets[0]
+            )
+        }
+        IpAddr::V6(_) => {
+            let nibbles: Vec<String> = hex(addr).chars().rev().map(String::from).collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        }
+    }
+}
+
+pub(crate) fn render(addr: IpAddr, family: Family, params: &FormatParams) -> HttpResponse {
+    let body = match params.format {
+        OutputFormat::Text => addr.to_string(),
+        OutputFormat::Expanded => expanded(addr),
+        OutputFormat::Hex => hex(addr),
+        OutputFormat::Int => value(addr).0.to_string(),
+        OutputFormat::Binary => binary(addr),
+        OutputFormat::Ptr => ptr(addr),
+        OutputFormat::Json => {
+            return HttpResponse::Ok()
+                .insert_header(("X-Address-Family", family.as_str()))
+                .json(Encodings {
+                    address: addr,
+                    family,
+                    expanded: expanded(addr),
+                    hex: hex(addr),
+                    int: value(addr).0.to_string(),
+                    binary: binary(addr),
+                    ptr: ptr(addr),
+                })
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .insert_header(("X-Address-Family", family.as_str()))
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::to_bytes;
+    use shuttle_runtime::__internals::serde_json;
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn encodes_ipv4() {
+        let addr = ip("10.0.1.255");
+        assert_eq!(expanded(addr), "10.0.1.255");
+        assert_eq!(hex(addr), "0a0001ff");
+        assert_eq!(value(addr), (0x0a0001ff, 32));
+        assert_eq!(binary(addr), "00001010000000000000000111111111");
+        assert_eq!(ptr(addr), "255.1.0.10.in-addr.arpa");
+    }
+
+    #[test]
+    fn encodes_ipv6() {
+        let addr = ip("2001:db8::1");
+        assert_eq!(expanded(addr), "2001:0db8:0000:0000:0000:0000:0000:0001");
+        assert_eq!(hex(addr), "20010db8000000000000000000000001");
+        assert_eq!(binary(addr).len(), 128);
+        assert_eq!(
+            ptr(addr),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[actix_web::test]
+    async fn renders_the_requested_format() {
+        let render_as = |format| render(ip("::1"), Family::Ipv6, &FormatParams { format });
+        let resp = render_as(OutputFormat::Int);
+        assert_eq!(resp.headers().get("X-Address-Family").unwrap(), "ipv6");
+        assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "1");
+
+        let body = to_bytes(render_as(OutputFormat::Json).into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["family"], "ipv6");
+        assert_eq!(json["int"], "1");
+        assert_eq!(json["address"], "::1");
+    }
+}
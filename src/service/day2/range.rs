@@ -0,0 +1,231 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+use actix_web::web::{Bytes, Query};
+use actix_web::HttpResponse;
+use futures::stream;
+use serde::{Deserialize, Serialize};
+use shuttle_runtime::__internals::serde_json;
+use super::addr::{self, AddrError, Family, Reason};
+use super::cidr::{self, Cidr};
+use super::cipher::CipherOp;
+
+const DEFAULT_LIMIT: u64 = 256;
+/// 单页最多返回的地址对数量
+const MAX_LIMIT: u64 = 4096;
+
+struct AddrRange {
+    first: u128,
+    last: u128,
+    bits: u32,
+    family: Family,
+}
+
+impl AddrRange {
+    /// 接受 CIDR（10.0.0.0/30）、区间（10.0.0.1-10.0.0.9）或单个地址
+    fn parse(param: &'static str, s: &str) -> Result<Self, AddrError> {
+        let invalid = || AddrError::new(param, s, None, Reason::InvalidRange);
+
+        if s.contains('/') {
+            let block = Cidr::from_str(s).map_err(|_| invalid())?;
+            let family = if block.network().is_ipv4() {
+                Family::Ipv4
+            } else {
+                Family::Ipv6
+            };
+            return Ok(Self::new(block.network(), block.last(), family));
+        }
+
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        let (first, family) = addr::parse_ip(param, first.trim())?;
+        let (last, _) = addr::parse_ip(param, last.trim())?;
+        if first.is_ipv4() != last.is_ipv4() || cidr::to_u128(first) > cidr::to_u128(last) {
+            return Err(invalid());
+        }
+        Ok(Self::new(first, last, family))
+    }
+
+    fn new(first: IpAddr, last: IpAddr, family: Family) -> Self {
+        Self {
+            first: cidr::to_u128(first),
+            last: cidr::to_u128(last),
+            bits: if first.is_ipv4() { 32 } else { 128 },
+            family,
+        }
+    }
+
+    /// 地址个数减一，避免 ::/0 时溢出
+    fn span(&self) -> u128 {
+        self.last - self.first
+    }
+
+    fn nth(&self, i: u128) -> Option<u128> {
+        (i <= self.span()).then(|| self.first + i)
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RangeParams {
+    from: String,
+    key: Option<String>,
+    to: Option<String>,
+    op: Option<CipherOp>,
+    #[serde(default)]
+    offset: u64,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct RangePair {
+    from: IpAddr,
+    to: IpAddr,
+    /// 按目标区间枚举时，无解的地址对为 null
+    key: Option<IpAddr>,
+}
+
+enum Target {
+    Key(u128),
+    Range(AddrRange),
+}
+
+pub(crate) async fn range(params: Query<RangeParams>) -> Result<HttpResponse, AddrError> {
+    let source = AddrRange::parse("from", &params.from)?;
+    let target = match (&params.key, &params.to) {
+        (Some(key), None) => {
+            let (addr, _) = addr::parse_ip("key", key)?;
+            if addr.is_ipv4() != (source.bits == 32) {
+                return Err(AddrError::new("key", key, None, Reason::FamilyMismatch));
+            }
+            Target::Key(cidr::to_u128(addr))
+        }
+        (None, Some(to)) => {
+            let target = AddrRange::parse("to", to)?;
+            if target.bits != source.bits {
+                return Err(AddrError::new("to", to, None, Reason::FamilyMismatch));
+            }
+            if target.span() != source.span() {
+                return Err(AddrError::new("to", to, None, Reason::RangeSizeMismatch));
+            }
+            Target::Range(target)
+        }
+        _ => {
+            return Err(AddrError::malformed(
+                "Invalid query",
+                "exactly one of `key` or `to` is required".to_string(),
+            ))
+        }
+    };
+
+    // limit=0 时 X-Next-Offset 不会前进，客户端会一直请求同一页
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = params.offset as u128;
+    let end = offset + limit as u128;
+    let cipher = params
+        .op
+        .unwrap_or_else(|| CipherOp::default_for(source.bits))
+        .cipher();
+    let bits = source.bits;
+    let family = source.family;
+
+    let mut response = HttpResponse::Ok();
+    response.content_type("application/x-ndjson");
+    if source.nth(end).is_some() {
+        response.insert_header(("X-Next-Offset", end.to_string()));
+    }
+
+    let pairs = (offset..end).map_while(move |i| {
+        let from = source.nth(i)?;
+        let (to, key) = match &target {
+            Target::Key(key) => (cipher.encrypt(from, *key, bits), Some(*key)),
+            Target::Range(range) => {
+                let to = range.nth(i)?;
+                (to, cipher.derive_key(from, to, bits))
+            }
+        };
+        let pair = RangePair {
+            from: super::to_addr(from, family),
+            to: super::to_addr(to, family),
+            key: key.map(|k| super::to_addr(k, family)),
+        };
+        let line = serde_json::to_string(&pair).unwrap_or_default();
+        Some(Ok::<_, actix_web::Error>(Bytes::from(line + "\n")))
+    });
+
+    Ok(response.streaming(stream::iter(pairs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::to_bytes;
+    use super::*;
+
+    async fn lines(query: &str) -> Result<(Vec<String>, Option<String>), AddrError> {
+        let resp = range(Query::from_query(query).unwrap()).await?;
+        let next = resp
+            .headers()
+            .get("X-Next-Offset")
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        Ok((body.lines().map(String::from).collect(), next))
+    }
+
+    #[test]
+    fn parses_blocks_ranges_and_addresses() {
+        let block = AddrRange::parse("from", "10.0.0.5/30").unwrap();
+        assert_eq!((block.first, block.span(), block.bits), (0x0a000004, 3, 32));
+        let range = AddrRange::parse("from", "10.0.0.1 - 10.0.0.9").unwrap();
+        assert_eq!((range.span(), range.nth(8), range.nth(9)), (8, Some(0x0a000009), None));
+        let single = AddrRange::parse("from", "::1").unwrap();
+        assert_eq!((single.span(), single.bits), (0, 128));
+        assert_eq!(AddrRange::parse("from", "::/0").unwrap().span(), u128::MAX);
+
+        for bad in ["10.0.0.9-10.0.0.1", "10.0.0.1-::1", "10.0.0.0/40"] {
+            assert!(AddrRange::parse("from", bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[actix_web::test]
+    async fn pages_through_a_key() {
+        let (pairs, next) = lines("from=10.0.0.0/30&key=0.0.0.1&limit=2").await.unwrap();
+        assert_eq!(
+            pairs,
+            [
+                r#"{"from":"10.0.0.0","to":"10.0.0.1","key":"0.0.0.1"}"#,
+                r#"{"from":"10.0.0.1","to":"10.0.0.2","key":"0.0.0.1"}"#,
+            ]
+        );
+        assert_eq!(next.as_deref(), Some("2"));
+
+        let (pairs, next) = lines("from=10.0.0.0/30&key=0.0.0.1&offset=2").await.unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(next, None);
+    }
+
+    #[actix_web::test]
+    async fn zero_limit_still_advances() {
+        let (pairs, next) = lines("from=10.0.0.0/30&key=0.0.0.1&limit=0").await.unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(next.as_deref(), Some("1"));
+    }
+
+    #[actix_web::test]
+    async fn derives_keys_between_ranges() {
+        let (pairs, _) = lines("from=10.0.0.0-10.0.0.1&to=10.0.1.0-10.0.1.1&op=xor").await.unwrap();
+        assert_eq!(
+            pairs,
+            [
+                r#"{"from":"10.0.0.0","to":"10.0.1.0","key":"0.0.1.0"}"#,
+                r#"{"from":"10.0.0.1","to":"10.0.1.1","key":"0.0.1.0"}"#,
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn rejects_mismatched_targets() {
+        let err = |query: &'static str| async move { lines(query).await.unwrap_err().to_string() };
+        assert_eq!(err("from=10.0.0.0/30").await, "exactly one of `key` or `to` is required");
+        assert_eq!(err("from=10.0.0.0/30&key=::1").await, "`key`: address family differs from the other address");
+        assert_eq!(err("from=10.0.0.0/30&to=10.0.1.0/29").await, "`to`: ranges differ in size");
+        assert_eq!(err("from=::/126&to=10.0.1.0/30").await, "`to`: address family differs from the other address");
+    }
+}
@@ -1,10 +1,35 @@
+use std::collections::HashMap;
+use std::sync::Arc;
 use actix_web::{HttpRequest, HttpResponse, HttpMessage, web};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::web::Query;
 use cargo_manifest::Manifest;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use shuttle_runtime::__internals::serde_json;
 use toml::Value;
+use diagnostic::Diagnostic;
+use lint::Lint;
+use media::InputFormat;
+use negotiate::OutputFormat;
+use policy::Violation;
+use quantity::Amount;
+use report::Report;
 
-#[derive(Debug)]
+mod batch;
+mod diagnostic;
+mod lint;
+mod media;
+mod negotiate;
+mod policy;
+mod quantity;
+mod report;
+mod store;
+mod workspace;
+
+pub(crate) use policy::Policy;
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
 enum OrderQuantity {
     U32(u32),
     String(String),
@@ -32,102 +57,467 @@ impl<'de> Deserialize<'de> for OrderQuantity {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 struct OrderItem {
     item: Option<String>,
     quantity: Option<OrderQuantity>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 struct PackageMetadata {
     orders: Option<Vec<OrderItem>>,
 }
 
-#[derive(Deserialize, Debug)]
-struct Package {
-    name: String,
-    authors: Option<Vec<String>>,
-    keywords: Option<Vec<String>>,
-    #[serde(rename = "rust-version")]
-    rust_version: Option<String>,
-    metadata: Option<PackageMetadata>,
+#[derive(Deserialize)]
+struct ManifestParams {
+    /// 返回完整的规范化 manifest，而不只是订单
+    #[serde(default)]
+    full: bool,
+    /// 列出每条订单的校验结果
+    #[serde(default)]
+    report: bool,
+    /// 同名订单合并数量
+    #[serde(default)]
+    aggregate: bool,
+    /// 没有 Content-Type（或只是 text/plain 等通用类型）时根据内容判断格式
+    #[serde(default)]
+    sniff: bool,
+    /// 运行 lint 规则，有 error 时拒绝，warning 的规则 ID 放在 X-Lint-Warnings 头里
+    #[serde(default)]
+    lint: bool,
+    /// 逗号分隔的 lint 规则 ID，这些规则不会运行
+    allow: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct Order {
+    item: String,
+    /// 已换算到该物品的目标单位
+    quantity: Amount,
+    /// 计数类物品没有单位
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Orders<'a> {
+    orders: &'a [Order],
 }
 
-#[derive(Deserialize, Debug)]
-struct TomlContent {
-    package: Package,
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum RejectReason {
+    MissingItem,
+    MissingQuantity,
+    NonInteger,
+    Negative,
+    OutOfRange,
+    UnknownUnit,
+    /// 单位的量纲与物品配置的单位不同，如 kg 与 l
+    IncompatibleUnit,
 }
 
-async fn manifest(body: String, req: HttpRequest) -> HttpResponse {
-    let content: Result<Manifest<PackageMetadata, Value>, String> = match req.content_type() {
-        "application/toml" => {
-            toml::from_str(&body).map_err(|e| format!("TOML parsing error: {}", e))
+impl RejectReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::MissingItem => "missing_item",
+            RejectReason::MissingQuantity => "missing_quantity",
+            RejectReason::NonInteger => "non_integer",
+            RejectReason::Negative => "negative",
+            RejectReason::OutOfRange => "out_of_range",
+            RejectReason::UnknownUnit => "unknown_unit",
+            RejectReason::IncompatibleUnit => "incompatible_unit",
         }
-        "application/yaml" => {
-            serde_yaml::from_str(&body).map_err(|e| format!("YAML parsing error: {}", e))
+    }
+}
+
+impl OrderItem {
+    /// ?report 使用的严格校验，缺少 item 的订单记为 missing_item
+    fn validate(&self, policy: &Policy) -> Result<Order, RejectReason> {
+        let item = self.item.clone().ok_or(RejectReason::MissingItem)?;
+        self.normalize(item, policy)
+    }
+
+    /// 默认输出沿用原来的过滤方式：缺少 item 时物品名为空，只按数量取舍
+    fn accept(&self, policy: &Policy) -> Option<Order> {
+        self.normalize(self.item.clone().unwrap_or_default(), policy).ok()
+    }
+
+    /// 数量按 policy 中该物品的规则换算，没有配置的物品只接受整数个数
+    fn normalize(&self, item: String, policy: &Policy) -> Result<Order, RejectReason> {
+        let quantity = self.quantity.as_ref().ok_or(RejectReason::MissingQuantity)?;
+        let (quantity, unit) = policy.item(&item).normalize(quantity)?;
+        Ok(Order {
+            unit: unit.map(str::to_string),
+            item,
+            quantity,
+        })
+    }
+}
+
+fn order_items(metadata: Option<&PackageMetadata>) -> &[OrderItem] {
+    metadata
+        .and_then(|v| v.orders.as_deref())
+        .unwrap_or_default()
+}
+
+fn accepted_orders(metadata: Option<&PackageMetadata>, policy: &Policy) -> Vec<Order> {
+    order_items(metadata)
+        .iter()
+        .filter_map(|order| order.accept(policy))
+        .collect()
+}
+
+/// 同名且同单位的订单合并数量，按首次出现的顺序输出
+fn aggregate(orders: Vec<Order>) -> Vec<Order> {
+    let mut index: HashMap<(String, Option<String>), usize> = HashMap::new();
+    let mut merged: Vec<Order> = vec![];
+    for order in orders {
+        let key = (order.item.clone(), order.unit.clone());
+        match index.get(&key) {
+            Some(&i) => merged[i].quantity = merged[i].quantity + order.quantity,
+            None => {
+                index.insert(key, merged.len());
+                merged.push(order);
+            }
         }
-        "application/json" => {
-            serde_json::from_str(&body).map_err(|e| format!("JSON parsing error: {}", e))
+    }
+    merged
+}
+
+fn render_orders(format: OutputFormat, orders: &[Order]) -> Result<String, String> {
+    match format {
+        OutputFormat::Text => Ok(orders
+            .iter()
+            .map(|o| match &o.unit {
+                Some(unit) => format!("{}: {} {}", o.item, o.quantity, unit),
+                None => format!("{}: {}", o.item, o.quantity),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")),
+        OutputFormat::Csv => Ok(std::iter::once("item,quantity,unit".to_string())
+            .chain(orders.iter().map(|o| {
+                format!(
+                    "{},{},{}",
+                    negotiate::csv_field(&o.item),
+                    o.quantity,
+                    negotiate::csv_field(o.unit.as_deref().unwrap_or_default())
+                )
+            }))
+            .map(|line| line + "\r\n")
+            .collect()),
+        _ => format.serialize(&Orders { orders }),
+    }
+}
+
+fn respond(format: OutputFormat, body: Result<String, String>) -> HttpResponse {
+    match body {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(format.content_type())
+            .body(body),
+        Err(err) => {
+            eprintln!("Failed to render: {:?}", err);
+            HttpResponse::InternalServerError().finish()
         }
-        _ => return HttpResponse::UnsupportedMediaType().finish(),
+    }
+}
+
+#[derive(Serialize)]
+struct Violations<'a> {
+    violations: &'a [Violation],
+}
+
+/// 纯文本响应体只包含各条规则的提示，规则名放在 X-Policy-Violations 头里
+fn reject(format: OutputFormat, violations: &[Violation]) -> HttpResponse {
+    let body = match format {
+        OutputFormat::Text => Ok(violations
+            .iter()
+            .map(|v| v.message())
+            .collect::<Vec<_>>()
+            .join("\n")),
+        OutputFormat::Csv => Ok(std::iter::once("rule,message".to_string())
+            .chain(violations.iter().map(|v| {
+                format!(
+                    "{},{}",
+                    negotiate::csv_field(v.rule()),
+                    negotiate::csv_field(v.message())
+                )
+            }))
+            .map(|line| line + "\r\n")
+            .collect()),
+        _ => format.serialize(&Violations { violations }),
     };
+    let rules = violations
+        .iter()
+        .map(|v| v.rule())
+        .collect::<Vec<_>>()
+        .join(", ");
 
-    match content {
-        Ok(content) => {
-            let mut response_body: Option<String> = None;
-
-            if let Some(pkg) = &content.package {
-                if !pkg
-                    .keywords
-                    .as_ref()
-                    .and_then(|keywords| keywords.as_ref().as_local())
-                    .map_or(false, |kws| kws.contains(&"Christmas 2024".to_string()))
-                {
-                    return HttpResponse::BadRequest().body("Magic keyword not provided");
-                }
-
-                response_body =
-                    pkg.metadata
-                        .as_ref()
-                        .and_then(|v| v.orders.as_ref())
-                        .map(|orders| {
-                            orders
-                                .into_iter()
-                                .filter_map(|order| {
-                                    // 安全地处理 quantity 字段，避免类型错误导致的崩溃
-                                    if let Some(quantity) = &order.quantity {
-                                        match quantity {
-                                            OrderQuantity::U32(quantity) => Some(format!(
-                                                "{}: {}",
-                                                order.item.clone().unwrap_or_default(),
-                                                quantity
-                                            )),
-                                            _ => None,
-                                        }
-                                    } else {
-                                        // 如果没有 quantity，忽略该订单
-                                        None
-                                    }
-                                })
-                                .collect::<Vec<_>>()
-                                .join("\n")
-                        });
-            }
+    match body {
+        Ok(body) => HttpResponse::BadRequest()
+            .content_type(format.content_type())
+            .insert_header(("X-Policy-Violations", rules))
+            .body(body),
+        Err(err) => {
+            eprintln!("Failed to render: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
 
-            match response_body {
-                Some(body) if !body.is_empty() => HttpResponse::Ok().body(body),
-                _ => HttpResponse::NoContent().finish(),
-            }
+#[derive(Serialize)]
+struct InvalidManifest<'a> {
+    error: &'static str,
+    diagnostic: &'a Diagnostic,
+}
+
+/// 纯文本响应体第一行保持 "Invalid manifest"，后面是诊断信息；
+/// 出错位置同时放在 X-Parse-Error 头里，结构化格式返回完整的诊断信息
+fn invalid(format: OutputFormat, diagnostic: &Diagnostic) -> HttpResponse {
+    let body = match format {
+        OutputFormat::Text => Ok(format!("Invalid manifest\n{}", diagnostic)),
+        OutputFormat::Csv => Ok(format!(
+            "error,location,message\r\nInvalid manifest,{},{}\r\n",
+            negotiate::csv_field(&diagnostic.location()),
+            negotiate::csv_field(&diagnostic.to_string())
+        )),
+        _ => format.serialize(&InvalidManifest {
+            error: "Invalid manifest",
+            diagnostic,
+        }),
+    };
+
+    match body {
+        Ok(body) => HttpResponse::BadRequest()
+            .content_type(format.content_type())
+            .insert_header(("X-Parse-Error", diagnostic.location()))
+            .body(body),
+        Err(err) => {
+            eprintln!("Failed to render: {:?}", err);
+            HttpResponse::InternalServerError().finish()
         }
+    }
+}
+
+#[derive(Debug)]
+enum ParseError {
+    UnsupportedMediaType,
+    Invalid(Diagnostic),
+}
+
+fn parse_manifest(
+    body: &str,
+    content_type: &str,
+    sniff: bool,
+) -> Result<Manifest<PackageMetadata, Value>, ParseError> {
+    match InputFormat::detect(content_type, body, sniff)? {
+        InputFormat::Toml => {
+            toml::from_str(body).map_err(|e| ParseError::Invalid(Diagnostic::toml(e, body)))
+        }
+        InputFormat::Yaml => {
+            serde_yaml::from_str(body).map_err(|e| ParseError::Invalid(Diagnostic::yaml(e, body)))
+        }
+        InputFormat::Json => {
+            serde_json::from_str(body).map_err(|e| ParseError::Invalid(Diagnostic::json(e, body)))
+        }
+    }
+}
+
+async fn manifest(
+    body: String,
+    req: HttpRequest,
+    params: Query<ManifestParams>,
+    policy: web::Data<Arc<Policy>>,
+) -> HttpResponse {
+    let Some(format) = OutputFormat::negotiate(&req) else {
+        return HttpResponse::NotAcceptable().finish();
+    };
+
+    let mut content = match parse_manifest(&body, req.content_type(), params.sniff) {
+        Ok(content) => content,
+        Err(ParseError::UnsupportedMediaType) => {
+            return HttpResponse::UnsupportedMediaType().finish()
+        }
+        Err(ParseError::Invalid(diagnostic)) => return invalid(format, &diagnostic),
+    };
+
+    workspace::resolve(&mut content, None);
+    if let Some(pkg) = &content.package {
+        let violations = policy.check(pkg, &content);
+        if !violations.is_empty() {
+            return reject(format, &violations);
+        }
+    }
+
+    let lints = if params.lint {
+        let allow = match lint::parse_allow(params.allow.as_deref()) {
+            Ok(allow) => allow,
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        };
+        lint::lint(&content, &policy, &allow)
+    } else {
+        vec![]
+    };
+    if lints.iter().any(Lint::is_error) {
+        return lint_errors(format, &lints);
+    }
+
+    let mut resp = orders_response(format, &params, &content, &policy);
+    let warnings = lint_rules(lints.iter());
+    if !warnings.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&warnings) {
+            resp.headers_mut()
+                .insert(HeaderName::from_static("x-lint-warnings"), value);
+        }
+    }
+    resp
+}
+
+/// 去重后的规则 ID，按首次出现的顺序
+fn lint_rules<'a>(lints: impl Iterator<Item = &'a Lint>) -> String {
+    let mut rules: Vec<&str> = vec![];
+    for rule in lints.map(Lint::rule) {
+        if !rules.contains(&rule) {
+            rules.push(rule);
+        }
+    }
+    rules.join(", ")
+}
+
+/// 响应体包含全部 lint 结果（含 warning），error 的规则 ID 放在 X-Lint-Errors 头里
+fn lint_errors(format: OutputFormat, lints: &[Lint]) -> HttpResponse {
+    let rules = lint_rules(lints.iter().filter(|l| l.is_error()));
+    match lint::render(format, lints) {
+        Ok(body) => HttpResponse::BadRequest()
+            .content_type(format.content_type())
+            .insert_header(("X-Lint-Errors", rules))
+            .body(body),
         Err(err) => {
-            eprintln!("Failed to parse: {:?}", err);
-            HttpResponse::BadRequest().body("Invalid manifest")
+            eprintln!("Failed to render: {:?}", err);
+            HttpResponse::InternalServerError().finish()
         }
     }
 }
 
+fn orders_response(
+    format: OutputFormat,
+    params: &ManifestParams,
+    content: &Manifest<PackageMetadata, Value>,
+    policy: &Policy,
+) -> HttpResponse {
+    if params.full {
+        return match format {
+            OutputFormat::Csv => HttpResponse::NotAcceptable().finish(),
+            // 纯文本下以 TOML 输出完整 manifest
+            OutputFormat::Text => {
+                respond(OutputFormat::Toml, OutputFormat::Toml.serialize(content))
+            }
+            _ => respond(format, format.serialize(content)),
+        };
+    }
+
+    let metadata = content.package.as_ref().and_then(|pkg| pkg.metadata.as_ref());
+    if params.report {
+        let report = Report::new(order_items(metadata), policy);
+        // 报告是结构化数据，纯文本请求时按 JSON 返回
+        let format = match format {
+            OutputFormat::Text => OutputFormat::Json,
+            _ => format,
+        };
+        return respond(format, report.render(format));
+    }
+
+    let mut orders = accepted_orders(metadata, policy);
+    if params.aggregate {
+        orders = aggregate(orders);
+    }
+    if orders.is_empty() {
+        return HttpResponse::NoContent().finish();
+    }
+    respond(format, render_orders(format, &orders))
+}
+
 
 pub(crate) fn scope() -> actix_web::Scope {
     web::scope("5")
         .route("/manifest", web::post().to(manifest))
-}
\ No newline at end of file
+        .route("/manifests", web::post().to(batch::manifests))
+        .route("/workspace", web::post().to(workspace::workspace))
+        .route("/lint", web::post().to(lint::lint_manifest))
+        .route("/store", web::post().to(store::store))
+        .route("/packages/{name}", web::get().to(store::list))
+        .route("/totals", web::get().to(store::totals))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(orders: &str) -> PackageMetadata {
+        toml::from_str(orders).expect("valid metadata")
+    }
+
+    const ORDERS: &str = r#"
+        orders = [
+            { item = "Toy car", quantity = 2 },
+            { quantity = 3 },
+            { item = "Lego brick", quantity = 1.5 },
+            { item = "Doll" },
+        ]
+    "#;
+
+    #[test]
+    fn default_output_keeps_orders_without_item() {
+        let metadata = metadata(ORDERS);
+        let orders = accepted_orders(Some(&metadata), &Policy::default());
+        let text = render_orders(OutputFormat::Text, &orders).unwrap();
+        assert_eq!(text, "Toy car: 2\n: 3");
+    }
+
+    #[test]
+    fn report_rejects_orders_without_item() {
+        let metadata = metadata(ORDERS);
+        let policy = Policy::default();
+        let reasons: Vec<_> = metadata
+            .orders
+            .as_deref()
+            .unwrap()
+            .iter()
+            .map(|o| o.validate(&policy).err())
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                None,
+                Some(RejectReason::MissingItem),
+                Some(RejectReason::NonInteger),
+                Some(RejectReason::MissingQuantity),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn invalid_text_body_includes_diagnostic() {
+        let body = "[package]\nname = 1 2\n";
+        let Err(ParseError::Invalid(diagnostic)) = parse_manifest(body, "application/toml", false) else {
+            panic!("manifest should not parse");
+        };
+        let resp = invalid(OutputFormat::Text, &diagnostic);
+        assert_eq!(resp.status(), 400);
+        assert_eq!(resp.headers().get("X-Parse-Error").unwrap(), "toml:2:10");
+        let text = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let text = std::str::from_utf8(&text).unwrap();
+        assert_eq!(text, format!("Invalid manifest\n{}", diagnostic));
+        assert!(text.starts_with("Invalid manifest\nTOML parse error at line 2, column 10: "));
+        assert!(text.ends_with("2 | name = 1 2\n  |          ^"));
+    }
+
+    #[test]
+    fn aggregates_same_item_and_unit() {
+        let metadata = metadata(
+            r#"orders = [{ item = "Toy car", quantity = 2 }, { item = "Doll", quantity = 1 }, { item = "Toy car", quantity = 5 }]"#,
+        );
+        let orders = aggregate(accepted_orders(Some(&metadata), &Policy::default()));
+        let text = render_orders(OutputFormat::Text, &orders).unwrap();
+        assert_eq!(text, "Toy car: 7\nDoll: 1");
+    }
+}
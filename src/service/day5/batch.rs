@@ -0,0 +1,277 @@
+use std::sync::Arc;
+use actix_multipart::{Multipart, MultipartError};
+use cargo_manifest::WorkspacePackage;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use serde::Serialize;
+use super::diagnostic::Diagnostic;
+use super::negotiate::OutputFormat;
+use super::policy::{Policy, Violation};
+use super::{accepted_orders, aggregate, parse_manifest, respond, workspace, Order, ParseError};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Accepted,
+    /// 未通过 policy 检查
+    Rejected,
+    /// 无法解析
+    Invalid,
+}
+
+#[derive(Serialize)]
+pub(super) struct ManifestResult {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    package: Option<String>,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diagnostic: Option<Diagnostic>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    violations: Vec<Violation>,
+    /// 同一 manifest 内已按物品合并
+    orders: Vec<Order>,
+}
+
+#[derive(Serialize)]
+pub(super) struct BatchResult {
+    manifests: Vec<ManifestResult>,
+    /// 所有通过检查的 manifest 的合计
+    totals: Vec<Order>,
+}
+
+impl BatchResult {
+    pub(super) fn new(manifests: Vec<ManifestResult>) -> Self {
+        let totals = aggregate(
+            manifests
+                .iter()
+                .flat_map(|m| m.orders.iter().cloned())
+                .collect(),
+        );
+        Self { manifests, totals }
+    }
+}
+
+impl ManifestResult {
+    pub(super) fn invalid(name: String, error: String) -> Self {
+        Self {
+            name,
+            package: None,
+            status: Status::Invalid,
+            error: Some(error),
+            diagnostic: None,
+            violations: vec![],
+            orders: vec![],
+        }
+    }
+}
+
+/// 解析单个 manifest；workspace 为 None 时从 manifest 自身的 [workspace.package] 继承字段
+pub(super) fn check(
+    name: String,
+    body: &str,
+    content_type: &str,
+    workspace: Option<&WorkspacePackage>,
+    policy: &Policy,
+) -> ManifestResult {
+    let mut content = match parse_manifest(body, content_type, false) {
+        Ok(content) => content,
+        Err(ParseError::UnsupportedMediaType) => {
+            return ManifestResult::invalid(name, format!("Unsupported media type {}", content_type))
+        }
+        Err(ParseError::Invalid(diagnostic)) => {
+            return ManifestResult {
+                diagnostic: Some(diagnostic),
+                ..ManifestResult::invalid(name, "Invalid manifest".to_string())
+            }
+        }
+    };
+    workspace::resolve(&mut content, workspace);
+
+    let mut result = ManifestResult {
+        name,
+        package: None,
+        status: Status::Invalid,
+        error: None,
+        diagnostic: None,
+        violations: vec![],
+        orders: vec![],
+    };
+    if let Some(pkg) = &content.package {
+        result.package = Some(pkg.name.clone());
+        result.violations = policy.check(pkg, &content);
+        if !result.violations.is_empty() {
+            result.status = Status::Rejected;
+            return result;
+        }
+    }
+
+    result.status = Status::Accepted;
+    result.orders = aggregate(accepted_orders(
+        content.package.as_ref().and_then(|pkg| pkg.metadata.as_ref()),
+        policy,
+    ));
+    result
+}
+
+/// 单个 part（一份 manifest）的大小上限
+pub(super) const MAX_PART: usize = 1 << 20;
+/// 整个上传的大小上限
+pub(super) const MAX_PAYLOAD: usize = 8 << 20;
+
+pub(super) struct Part {
+    /// 优先取 filename，没有时用字段名
+    pub(super) name: String,
+    pub(super) content_type: String,
+    pub(super) bytes: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub(super) enum ReadError {
+    Multipart(MultipartError),
+    /// 超过 MAX_PART 或 MAX_PAYLOAD
+    TooLarge,
+}
+
+impl From<MultipartError> for ReadError {
+    fn from(e: MultipartError) -> Self {
+        ReadError::Multipart(e)
+    }
+}
+
+impl ReadError {
+    /// 超出上限返回 413，其余按格式错误返回 400
+    pub(super) fn response(&self, context: &str) -> HttpResponse {
+        match self {
+            ReadError::TooLarge => HttpResponse::PayloadTooLarge().finish(),
+            ReadError::Multipart(e) => {
+                println!("Error while receiving {}: {}", context, e);
+                HttpResponse::BadRequest().finish()
+            }
+        }
+    }
+}
+
+pub(super) async fn read_parts(mut payload: Multipart) -> Result<Vec<Part>, ReadError> {
+    let mut parts = vec![];
+    let mut total = 0;
+    while let Some(item) = payload.next().await {
+        let mut field = item?;
+        let name = field
+            .content_disposition()
+            .get_filename()
+            .map(str::to_string)
+            .unwrap_or_else(|| field.name().to_string());
+        let content_type = field.content_type().essence_str().to_string();
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk?;
+            total += chunk.len();
+            if bytes.len() + chunk.len() > MAX_PART || total > MAX_PAYLOAD {
+                return Err(ReadError::TooLarge);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        parts.push(Part {
+            name,
+            content_type,
+            bytes,
+        });
+    }
+    Ok(parts)
+}
+
+/// 结果是结构化数据，纯文本请求时按 JSON 返回
+pub(super) fn structured_format(req: &HttpRequest) -> Option<OutputFormat> {
+    match OutputFormat::negotiate(req) {
+        Some(OutputFormat::Text) => Some(OutputFormat::Json),
+        Some(OutputFormat::Csv) | None => None,
+        format => format,
+    }
+}
+
+/// multipart 中每个 part 是一份 manifest，格式由 part 自己的 Content-Type 决定
+pub(super) async fn manifests(
+    payload: Multipart,
+    req: HttpRequest,
+    policy: web::Data<Arc<Policy>>,
+) -> HttpResponse {
+    let Some(format) = structured_format(&req) else {
+        return HttpResponse::NotAcceptable().finish();
+    };
+
+    let parts = match read_parts(payload).await {
+        Ok(parts) => parts,
+        Err(e) => return e.response("manifest"),
+    };
+
+    let manifests = parts
+        .into_iter()
+        .map(|part| match String::from_utf8(part.bytes) {
+            Ok(body) => check(part.name, &body, &part.content_type, None, &policy),
+            Err(_) => ManifestResult::invalid(part.name, "Invalid UTF-8".to_string()),
+        })
+        .collect();
+
+    respond(format, format.serialize(&BatchResult::new(manifests)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::error::PayloadError;
+    use actix_web::http::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+    use actix_web::web::Bytes;
+    use futures::stream;
+    use super::*;
+
+    const BOUNDARY: &str = "xmas";
+
+    fn multipart(parts: &[(&str, usize)]) -> Multipart {
+        let mut body = Vec::new();
+        for (name, size) in parts {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                    BOUNDARY, name
+                )
+                .as_bytes(),
+            );
+            body.extend(std::iter::repeat_n(b'a', *size));
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+
+        let mut headers = HeaderMap::new();
+        let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(&content_type).unwrap());
+        // 分块发送，与真实的请求体一样
+        let chunks: Vec<Result<Bytes, PayloadError>> = body
+            .chunks(64 << 10)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        Multipart::new(&headers, stream::iter(chunks))
+    }
+
+    #[actix_web::test]
+    async fn reads_parts_within_limits() {
+        let parts = read_parts(multipart(&[("a", 10), ("b", MAX_PART)])).await.unwrap();
+        let sizes: Vec<_> = parts.iter().map(|p| (p.name.as_str(), p.bytes.len())).collect();
+        assert_eq!(sizes, [("a", 10), ("b", MAX_PART)]);
+    }
+
+    #[actix_web::test]
+    async fn rejects_oversized_part() {
+        let err = read_parts(multipart(&[("a", MAX_PART + 1)])).await.err();
+        assert!(matches!(err, Some(ReadError::TooLarge)));
+    }
+
+    #[actix_web::test]
+    async fn rejects_oversized_payload() {
+        let parts = vec![("a", MAX_PART); MAX_PAYLOAD / MAX_PART + 1];
+        let err = read_parts(multipart(&parts)).await.err();
+        assert!(matches!(err, Some(ReadError::TooLarge)));
+    }
+}
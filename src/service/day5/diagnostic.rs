@@ -0,0 +1,172 @@
+use std::fmt;
+use serde::Serialize;
+use shuttle_runtime::__internals::serde_json;
+
+/// manifest 解析失败的位置信息，行列号从 1 开始
+#[derive(Serialize, Debug)]
+pub(crate) struct Diagnostic {
+    format: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+    /// 出错的那一行以及指向出错位置的 ^
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<String>,
+}
+
+/// 字节偏移转换为行列号，列按字符计
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// 去掉解析器自带的 " at line X column Y" 后缀，位置单独给出
+fn strip_location(message: String) -> String {
+    match message.rsplit_once(" at line ") {
+        Some((message, _)) => message.to_string(),
+        None => message,
+    }
+}
+
+fn snippet(source: &str, line: usize, column: usize, width: usize) -> Option<String> {
+    let text = source.lines().nth(line - 1)?;
+    let gutter = line.to_string();
+    let pad = " ".repeat(gutter.len());
+    // 跨行的 span 只标到行尾
+    let available = text.chars().count().saturating_sub(column - 1).max(1);
+    Some(format!(
+        "{pad} |\n{gutter} | {text}\n{pad} | {}{}",
+        " ".repeat(column - 1),
+        "^".repeat(width.clamp(1, available))
+    ))
+}
+
+impl Diagnostic {
+    fn new(format: &'static str, message: String, source: &str, at: Option<(usize, usize, usize)>) -> Self {
+        let (line, column, snippet) = match at {
+            Some((line, column, width)) if line > 0 => {
+                let column = column.max(1);
+                (Some(line), Some(column), snippet(source, line, column, width))
+            }
+            _ => (None, None, None),
+        };
+        Self {
+            format,
+            message,
+            line,
+            column,
+            snippet,
+        }
+    }
+
+    pub(super) fn toml(err: toml::de::Error, source: &str) -> Self {
+        let at = err.span().map(|span| {
+            let (line, column) = position(source, span.start);
+            (line, column, source.get(span).map_or(1, |s| s.chars().count()))
+        });
+        Self::new("toml", err.message().trim().to_string(), source, at)
+    }
+
+    pub(super) fn yaml(err: serde_yaml::Error, source: &str) -> Self {
+        let at = err.location().map(|loc| {
+            let (line, column) = position(source, loc.index());
+            (line, column, 1)
+        });
+        Self::new("yaml", strip_location(err.to_string()), source, at)
+    }
+
+    /// serde_json 的列号是字节偏移，换算成字符数
+    pub(super) fn json(err: serde_json::Error, source: &str) -> Self {
+        let column = match source.lines().nth(err.line().wrapping_sub(1)) {
+            Some(text) => text.char_indices().take_while(|(i, _)| *i < err.column()).count(),
+            None => err.column(),
+        };
+        let at = Some((err.line(), column, 1));
+        Self::new("json", strip_location(err.to_string()), source, at)
+    }
+
+    /// 用于 X-Parse-Error 头，如 "toml:3:12"
+    pub(super) fn location(&self) -> String {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{}:{}", self.format, line, column),
+            _ => self.format.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} parse error", self.format.to_uppercase())?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " at line {}, column {}", line, column)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(snippet) = &self.snippet {
+            write!(f, "\n{}", snippet)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_lines_and_characters() {
+        let source = "a = 1\nné = [\n";
+        assert_eq!(position(source, 0), (1, 1));
+        assert_eq!(position(source, 6), (2, 1));
+        // é 占两个字节，但只算一列
+        assert_eq!(position(source, source.find('[').unwrap()), (2, 6));
+        assert_eq!(position(source, 1000), (3, 1));
+    }
+
+    #[test]
+    fn json_error_has_location_and_snippet() {
+        let source = "{\n  \"package\": nope\n}";
+        let err = serde_json::from_str::<serde_json::Value>(source).unwrap_err();
+        let diagnostic = Diagnostic::json(err, source);
+        assert_eq!(diagnostic.location(), "json:2:15");
+        assert_eq!(
+            diagnostic.to_string(),
+            "JSON parse error at line 2, column 15: expected ident\n  |\n2 |   \"package\": nope\n  |               ^"
+        );
+    }
+
+    /// 插入符在 snippet 中指向的字符
+    fn pointed(diagnostic: &Diagnostic) -> char {
+        let snippet = diagnostic.snippet.as_deref().unwrap();
+        let lines: Vec<&str> = snippet.lines().collect();
+        let (text, caret) = (lines[1].split_once(" | ").unwrap().1, lines[2].split_once(" | ").unwrap().1);
+        let column = caret.chars().position(|c| c == '^').unwrap();
+        assert_eq!(Some(column + 1), diagnostic.column);
+        text.chars().nth(column).unwrap()
+    }
+
+    #[test]
+    fn carets_point_at_non_ascii_columns() {
+        let source = "[package]\nname = \"üñ\" x\n";
+        let err = toml::from_str::<toml::Table>(source).unwrap_err();
+        assert_eq!(pointed(&Diagnostic::toml(err, source)), 'x');
+
+        let source = "{\"näme\": \"ü\" x}";
+        let err = serde_json::from_str::<serde_json::Value>(source).unwrap_err();
+        assert_eq!(pointed(&Diagnostic::json(err, source)), 'x');
+
+        let source = "ä: 1\nnäme: ü\n  ö: 2\n";
+        let err = serde_yaml::from_str::<serde_yaml::Value>(source).unwrap_err();
+        assert_eq!(pointed(&Diagnostic::yaml(err, source)), ':');
+    }
+
+    #[test]
+    fn strips_parser_location_suffix() {
+        assert_eq!(strip_location("bad at line 1 column 2".to_string()), "bad");
+        assert_eq!(strip_location("bad".to_string()), "bad");
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Arc;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use actix_web::web::Query;
+use cargo_manifest::{Manifest, MaybeInherited};
+use serde::{Deserialize, Serialize};
+use toml::Value;
+use super::negotiate::{self, OutputFormat};
+use super::policy::{self, Policy};
+use super::{invalid, order_items, parse_manifest, respond, workspace, PackageMetadata, ParseError};
+
+/// 换算后超过该数量的订单给出警告
+const LARGE_QUANTITY: f64 = 10_000.0;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// 规则 ID 与级别，allow 参数中只能出现这里的 ID
+const RULES: &[(&str, Severity)] = &[
+    ("duplicate-item", Severity::Warning),
+    ("empty-item", Severity::Error),
+    ("large-quantity", Severity::Warning),
+    ("missing-description", Severity::Warning),
+    ("invalid-license", Severity::Error),
+    ("invalid-rust-version", Severity::Error),
+];
+
+#[derive(Serialize, Debug)]
+pub(super) struct Lint {
+    rule: &'static str,
+    severity: Severity,
+    message: String,
+    /// 出问题的字段，如 "package.metadata.orders[2].item"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+}
+
+impl Lint {
+    fn new(rule: &'static str, message: String, path: Option<String>) -> Self {
+        let severity = RULES
+            .iter()
+            .find(|(id, _)| *id == rule)
+            .map(|(_, severity)| *severity)
+            .expect("lint rule is registered");
+        Self {
+            rule,
+            severity,
+            message,
+            path,
+        }
+    }
+
+    pub(super) fn rule(&self) -> &str {
+        self.rule
+    }
+
+    pub(super) fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+/// "error[empty-item] package.metadata.orders[0].item: ..."
+fn line(lint: &Lint) -> String {
+    match &lint.path {
+        Some(path) => format!("{}[{}] {}: {}", lint.severity.as_str(), lint.rule, path, lint.message),
+        None => format!("{}[{}]: {}", lint.severity.as_str(), lint.rule, lint.message),
+    }
+}
+
+/// 逗号分隔的规则 ID，出现未知 ID 时返回它
+pub(super) fn parse_allow(allow: Option<&str>) -> Result<Vec<&str>, String> {
+    allow
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| match RULES.iter().any(|(rule, _)| *rule == id) {
+            true => Ok(id),
+            false => Err(format!("Unknown lint rule {:?}", id)),
+        })
+        .collect()
+}
+
+const SPDX_LICENSES: &[&str] = &[
+    "0BSD", "AFL-3.0", "AGPL-3.0", "AGPL-3.0-only", "AGPL-3.0-or-later", "Apache-1.1",
+    "Apache-2.0", "Artistic-2.0", "BSD-1-Clause", "BSD-2-Clause", "BSD-3-Clause",
+    "BSD-3-Clause-Clear", "BSL-1.0", "BlueOak-1.0.0", "CC-BY-4.0", "CC-BY-SA-4.0", "CC0-1.0",
+    "CDDL-1.0", "ECL-2.0", "EPL-1.0", "EPL-2.0", "EUPL-1.2", "GPL-2.0", "GPL-2.0-only",
+    "GPL-2.0-or-later", "GPL-3.0", "GPL-3.0-only", "GPL-3.0-or-later", "ISC", "LGPL-2.1",
+    "LGPL-2.1-only", "LGPL-2.1-or-later", "LGPL-3.0", "LGPL-3.0-only", "LGPL-3.0-or-later",
+    "MIT", "MIT-0", "MPL-2.0", "MS-PL", "NCSA", "OFL-1.1", "OpenSSL", "PostgreSQL", "Python-2.0",
+    "Unicode-3.0", "Unicode-DFS-2016", "Unlicense", "UPL-1.0", "WTFPL", "Zlib",
+];
+
+const SPDX_EXCEPTIONS: &[&str] = &[
+    "Classpath-exception-2.0", "GCC-exception-3.1", "LLVM-exception", "OpenJDK-assembly-exception-1.0",
+];
+
+/// 把 SPDX 表达式拆成 token，括号单独成为 token
+fn tokenize(expr: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    for word in expr.split_whitespace() {
+        let mut rest = word;
+        while !rest.is_empty() {
+            let end = rest.find(['(', ')']).unwrap_or(rest.len());
+            if end == 0 {
+                tokens.push(&rest[..1]);
+                rest = &rest[1..];
+            } else {
+                tokens.push(&rest[..end]);
+                rest = &rest[end..];
+            }
+        }
+    }
+    tokens
+}
+
+/// 按 SPDX 2.3 的表达式语法检查：
+/// expr = term { ("AND" | "OR") term }，term = "(" expr ")" | license ["WITH" exception]
+fn spdx_expr<'a>(tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>) -> Result<(), String> {
+    loop {
+        match tokens.next() {
+            Some("(") => {
+                spdx_expr(tokens)?;
+                if tokens.next() != Some(")") {
+                    return Err("unbalanced parentheses".to_string());
+                }
+            }
+            Some(id) => {
+                let license = id.strip_suffix('+').unwrap_or(id);
+                if !SPDX_LICENSES.contains(&license) && !license.starts_with("LicenseRef-") {
+                    return Err(format!("unknown license identifier {:?}", id));
+                }
+                if tokens.next_if_eq(&"WITH").is_some() {
+                    match tokens.next() {
+                        Some(exception) if SPDX_EXCEPTIONS.contains(&exception) => {}
+                        Some(exception) => {
+                            return Err(format!("unknown license exception {:?}", exception))
+                        }
+                        None => return Err("missing exception after WITH".to_string()),
+                    }
+                }
+            }
+            None => return Err("missing license identifier".to_string()),
+        }
+        match tokens.peek() {
+            Some(&"AND") | Some(&"OR") => {
+                tokens.next();
+            }
+            Some(&")") | None => return Ok(()),
+            Some(other) => return Err(format!("expected AND or OR, found {:?}", other)),
+        }
+    }
+}
+
+fn check_license(expr: &str) -> Result<(), String> {
+    if expr.contains('/') {
+        return Err("use \"OR\" instead of \"/\" to combine licenses".to_string());
+    }
+    let mut tokens = tokenize(expr).into_iter().peekable();
+    spdx_expr(&mut tokens)?;
+    match tokens.next() {
+        Some(extra) => Err(format!("unexpected {:?}", extra)),
+        None => Ok(()),
+    }
+}
+
+fn local<T>(field: &Option<MaybeInherited<T>>) -> Option<&T> {
+    field.as_ref().and_then(|v| v.as_ref().as_local())
+}
+
+/// 对解析后的 manifest 运行所有未被 allow 关闭的规则
+pub(super) fn lint(
+    manifest: &Manifest<PackageMetadata, Value>,
+    policy: &Policy,
+    allow: &[&str],
+) -> Vec<Lint> {
+    let mut lints = vec![];
+    let Some(pkg) = &manifest.package else {
+        return lints;
+    };
+
+    if local(&pkg.description).is_none_or(|d| d.trim().is_empty()) {
+        lints.push(Lint::new(
+            "missing-description",
+            "package has no description".to_string(),
+            Some("package.description".to_string()),
+        ));
+    }
+    if let Some(Err(e)) = local(&pkg.license).map(|l| check_license(l)) {
+        lints.push(Lint::new(
+            "invalid-license",
+            format!("license is not a valid SPDX expression: {}", e),
+            Some("package.license".to_string()),
+        ));
+    }
+    if let Some(v) = local(&pkg.rust_version) {
+        if policy::parse_version(v).is_none() {
+            lints.push(Lint::new(
+                "invalid-rust-version",
+                format!("rust-version {:?} is not of the form MAJOR.MINOR[.PATCH]", v),
+                Some("package.rust-version".to_string()),
+            ));
+        }
+    }
+
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for (i, order) in order_items(pkg.metadata.as_ref()).iter().enumerate() {
+        let path = format!("package.metadata.orders[{}]", i);
+        if let Some(item) = &order.item {
+            if item.trim().is_empty() {
+                lints.push(Lint::new(
+                    "empty-item",
+                    "item name is empty".to_string(),
+                    Some(format!("{}.item", path)),
+                ));
+            } else {
+                match seen.entry(item.trim()) {
+                    Entry::Occupied(first) => lints.push(Lint::new(
+                        "duplicate-item",
+                        format!("{:?} was already ordered at index {}", item, first.get()),
+                        Some(format!("{}.item", path)),
+                    )),
+                    Entry::Vacant(entry) => {
+                        entry.insert(i);
+                    }
+                }
+            }
+        }
+        if let Ok(accepted) = order.validate(policy) {
+            if accepted.quantity.as_f64() > LARGE_QUANTITY {
+                lints.push(Lint::new(
+                    "large-quantity",
+                    format!("quantity {} is larger than {}", accepted.quantity, LARGE_QUANTITY),
+                    Some(format!("{}.quantity", path)),
+                ));
+            }
+        }
+    }
+
+    lints.retain(|l| !allow.contains(&l.rule));
+    lints
+}
+
+#[derive(Serialize)]
+struct LintReport<'a> {
+    lints: &'a [Lint],
+    errors: usize,
+    warnings: usize,
+}
+
+pub(super) fn render(format: OutputFormat, lints: &[Lint]) -> Result<String, String> {
+    match format {
+        OutputFormat::Text => Ok(lints.iter().map(line).collect::<Vec<_>>().join("\n")),
+        OutputFormat::Csv => Ok(std::iter::once("rule,severity,path,message".to_string())
+            .chain(lints.iter().map(|l| {
+                format!(
+                    "{},{},{},{}",
+                    l.rule,
+                    l.severity.as_str(),
+                    negotiate::csv_field(l.path.as_deref().unwrap_or_default()),
+                    negotiate::csv_field(&l.message)
+                )
+            }))
+            .map(|line| line + "\r\n")
+            .collect()),
+        _ => {
+            let errors = lints.iter().filter(|l| l.is_error()).count();
+            format.serialize(&LintReport {
+                lints,
+                errors,
+                warnings: lints.len() - errors,
+            })
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct LintParams {
+    /// 逗号分隔的规则 ID，这些规则不会运行
+    allow: Option<String>,
+    #[serde(default)]
+    sniff: bool,
+}
+
+/// 只做检查，不处理订单；有 error 时也返回 200，由结果中的 errors 判断
+pub(super) async fn lint_manifest(
+    body: String,
+    req: HttpRequest,
+    params: Query<LintParams>,
+    policy: web::Data<Arc<Policy>>,
+) -> HttpResponse {
+    let Some(format) = OutputFormat::negotiate(&req) else {
+        return HttpResponse::NotAcceptable().finish();
+    };
+    let allow = match parse_allow(params.allow.as_deref()) {
+        Ok(allow) => allow,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let mut content = match parse_manifest(&body, req.content_type(), params.sniff) {
+        Ok(content) => content,
+        Err(ParseError::UnsupportedMediaType) => {
+            return HttpResponse::UnsupportedMediaType().finish()
+        }
+        Err(ParseError::Invalid(diagnostic)) => return invalid(format, &diagnostic),
+    };
+    workspace::resolve(&mut content, None);
+
+    respond(format, render(format, &lint(&content, &policy, &allow)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_manifest;
+    use super::*;
+
+    fn rules(manifest: &str, allow: &[&str]) -> Vec<&'static str> {
+        let manifest = parse_manifest(manifest, "application/toml", false).unwrap();
+        lint(&manifest, &Policy::default(), allow).into_iter().map(|l| l.rule).collect()
+    }
+
+    #[test]
+    fn validates_spdx_expressions() {
+        for ok in ["MIT", "MIT OR Apache-2.0", "(MIT AND Zlib) OR GPL-2.0+", "Apache-2.0 WITH LLVM-exception", "LicenseRef-Own"] {
+            assert_eq!(check_license(ok), Ok(()), "{}", ok);
+        }
+        assert_eq!(check_license("MIT/Apache-2.0"), Err("use \"OR\" instead of \"/\" to combine licenses".to_string()));
+        assert_eq!(check_license("Foo"), Err("unknown license identifier \"Foo\"".to_string()));
+        assert_eq!(check_license("(MIT"), Err("unbalanced parentheses".to_string()));
+        assert_eq!(check_license("MIT)"), Err("unexpected \")\"".to_string()));
+        assert_eq!(check_license("MIT WITH"), Err("missing exception after WITH".to_string()));
+        assert_eq!(check_license("MIT Zlib"), Err("expected AND or OR, found \"Zlib\"".to_string()));
+        assert_eq!(check_license(""), Err("missing license identifier".to_string()));
+    }
+
+    #[test]
+    fn parses_allow_lists() {
+        assert_eq!(parse_allow(None), Ok(vec![]));
+        assert_eq!(parse_allow(Some(" duplicate-item, ,large-quantity")), Ok(vec!["duplicate-item", "large-quantity"]));
+        assert_eq!(parse_allow(Some("nope")), Err("Unknown lint rule \"nope\"".to_string()));
+    }
+
+    const MANIFEST: &str = r#"
+        [package]
+        name = "gifts"
+        license = "MIT/Apache-2.0"
+        rust-version = "latest"
+
+        [[package.metadata.orders]]
+        item = "Toy car"
+        quantity = 2
+
+        [[package.metadata.orders]]
+        item = " "
+        quantity = 1
+
+        [[package.metadata.orders]]
+        item = "Toy car"
+        quantity = 20000
+    "#;
+
+    #[test]
+    fn reports_every_rule() {
+        assert_eq!(
+            rules(MANIFEST, &[]),
+            [
+                "missing-description",
+                "invalid-license",
+                "invalid-rust-version",
+                "empty-item",
+                "duplicate-item",
+                "large-quantity",
+            ]
+        );
+        assert_eq!(
+            rules(MANIFEST, &["missing-description", "duplicate-item", "large-quantity"]),
+            ["invalid-license", "invalid-rust-version", "empty-item"]
+        );
+        assert!(rules("[package]\nname = \"x\"\ndescription = \"gifts\"", &[]).is_empty());
+        assert!(rules("[workspace]\nmembers = []", &[]).is_empty());
+    }
+
+    #[test]
+    fn renders_lints() {
+        let manifest = parse_manifest(MANIFEST, "application/toml", false).unwrap();
+        let lints = lint(&manifest, &Policy::default(), &["missing-description", "invalid-rust-version"]);
+
+        let text = render(OutputFormat::Text, &lints).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("error[invalid-license] package.license: "));
+        assert_eq!(lines[1], "error[empty-item] package.metadata.orders[1].item: item name is empty");
+        assert_eq!(
+            lines[2],
+            "warning[duplicate-item] package.metadata.orders[2].item: \"Toy car\" was already ordered at index 0"
+        );
+
+        let json = render(OutputFormat::Json, &lints).unwrap();
+        assert!(json.ends_with(r#""errors":2,"warnings":2}"#), "{}", json);
+
+        let csv = render(OutputFormat::Csv, &lints).unwrap();
+        assert!(csv.starts_with("rule,severity,path,message\r\ninvalid-license,error,package.license,"));
+        assert_eq!(csv.lines().count(), 5);
+    }
+}
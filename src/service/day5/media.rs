@@ -0,0 +1,100 @@
+use shuttle_runtime::__internals::serde_json::{self, Value as JsonValue};
+use super::ParseError;
+
+/// manifest 请求体的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum InputFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl InputFormat {
+    fn from_essence(essence: &str) -> Option<Self> {
+        match essence {
+            "application/toml" | "text/x-toml" | "text/toml" => Some(InputFormat::Toml),
+            "application/yaml" | "application/x-yaml" | "text/yaml" | "text/x-yaml" => {
+                Some(InputFormat::Yaml)
+            }
+            "application/json" | "text/json" => Some(InputFormat::Json),
+            // 结构化语法后缀，如 application/vnd.cargo+toml
+            _ => match essence.rsplit_once('+')?.1 {
+                "toml" => Some(InputFormat::Toml),
+                "yaml" => Some(InputFormat::Yaml),
+                "json" => Some(InputFormat::Json),
+                _ => None,
+            },
+        }
+    }
+
+    /// 按内容猜测格式：JSON 与 TOML 语法较严格，先试；YAML 几乎什么都能解析，放最后
+    fn sniff(body: &str) -> Option<Self> {
+        if body.trim_start().starts_with('{') && serde_json::from_str::<JsonValue>(body).is_ok() {
+            return Some(InputFormat::Json);
+        }
+        if toml::from_str::<toml::Table>(body).is_ok() {
+            return Some(InputFormat::Toml);
+        }
+        if serde_yaml::from_str::<serde_yaml::Mapping>(body).is_ok() {
+            return Some(InputFormat::Yaml);
+        }
+        None
+    }
+
+    /// content_type 忽略参数与大小写；为空或是 text/plain 这类通用类型时，
+    /// 只有开启 sniff 才根据内容判断
+    pub(super) fn detect(content_type: &str, body: &str, sniff: bool) -> Result<Self, ParseError> {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if let Some(format) = Self::from_essence(&essence) {
+            return Ok(format);
+        }
+
+        let generic = matches!(
+            essence.as_str(),
+            "" | "text/plain" | "application/octet-stream"
+        );
+        if !(sniff && generic) {
+            return Err(ParseError::UnsupportedMediaType);
+        }
+        // 都解析不了时按 TOML 报告错误位置，以 { 开头的按 JSON
+        Ok(Self::sniff(body).unwrap_or(if body.trim_start().starts_with('{') {
+            InputFormat::Json
+        } else {
+            InputFormat::Toml
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(content_type: &str, body: &str, sniff: bool) -> Option<InputFormat> {
+        InputFormat::detect(content_type, body, sniff).ok()
+    }
+
+    #[test]
+    fn detects_declared_formats() {
+        assert_eq!(detect("application/toml", "", false), Some(InputFormat::Toml));
+        assert_eq!(detect("Text/YAML; charset=utf-8", "", false), Some(InputFormat::Yaml));
+        assert_eq!(detect("application/vnd.cargo+json", "", false), Some(InputFormat::Json));
+        assert_eq!(detect("application/vnd.cargo+xml", "", true), None);
+        assert_eq!(detect("text/html", "a = 1", true), None);
+    }
+
+    #[test]
+    fn sniffs_generic_types_only_when_enabled() {
+        assert_eq!(detect("text/plain", "a = 1", false), None);
+        assert_eq!(detect("", r#"{"a": 1}"#, true), Some(InputFormat::Json));
+        assert_eq!(detect("text/plain", "[package]\nname = \"x\"", true), Some(InputFormat::Toml));
+        assert_eq!(detect("application/octet-stream", "package:\n  name: x", true), Some(InputFormat::Yaml));
+        // 都解析不了时用于报告错误的格式
+        assert_eq!(detect("", "{ broken", true), Some(InputFormat::Json));
+        assert_eq!(detect("", "- [", true), Some(InputFormat::Toml));
+    }
+}
@@ -0,0 +1,137 @@
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use serde::Serialize;
+use shuttle_runtime::__internals::serde_json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    /// 原有的 "item: quantity" 逐行输出
+    Text,
+    Json,
+    Yaml,
+    Toml,
+    Csv,
+}
+
+impl OutputFormat {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "text/plain" | "text/*" | "*/*" => Some(OutputFormat::Text),
+            "application/json" | "application/*" => Some(OutputFormat::Json),
+            "application/yaml" | "application/x-yaml" | "text/yaml" => Some(OutputFormat::Yaml),
+            "application/toml" | "text/x-toml" => Some(OutputFormat::Toml),
+            "text/csv" => Some(OutputFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text/plain; charset=utf-8",
+            OutputFormat::Json => "application/json",
+            OutputFormat::Yaml => "application/yaml",
+            OutputFormat::Toml => "application/toml",
+            OutputFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    /// 按 Accept 的 q 值挑选第一个支持的格式；没有 Accept 时沿用纯文本，
+    /// 全部不支持时返回 None（406）
+    pub(crate) fn negotiate(req: &HttpRequest) -> Option<Self> {
+        let Some(accept) = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.trim().is_empty())
+        else {
+            return Some(OutputFormat::Text);
+        };
+
+        let mut ranges: Vec<(&str, f32)> = accept
+            .split(',')
+            .map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let media_type = parts.next().unwrap_or_default();
+                let q = parts
+                    .filter_map(|p| p.strip_prefix("q="))
+                    .find_map(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (media_type, q)
+            })
+            .filter(|(_, q)| *q > 0.0)
+            .collect();
+        // sort_by 是稳定排序，q 相同时保持客户端给出的顺序
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        ranges
+            .into_iter()
+            .find_map(|(media_type, _)| Self::from_media_type(&media_type.to_ascii_lowercase()))
+    }
+
+    /// 结构化格式的序列化；Text 与 Csv 只适用于订单列表，由调用方处理
+    pub(crate) fn serialize<T: Serialize>(&self, value: &T) -> Result<String, String> {
+        match self {
+            OutputFormat::Json => serde_json::to_string(value).map_err(|e| e.to_string()),
+            OutputFormat::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+            OutputFormat::Toml => toml::to_string(value).map_err(|e| e.to_string()),
+            OutputFormat::Text | OutputFormat::Csv => {
+                Err(format!("{} is not a structured format", self.content_type()))
+            }
+        }
+    }
+}
+
+/// RFC 4180：含逗号、引号或换行的字段用双引号包裹
+pub(crate) fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use super::*;
+
+    fn negotiate(accept: Option<&str>) -> Option<OutputFormat> {
+        let req = match accept {
+            Some(accept) => TestRequest::default().insert_header((header::ACCEPT, accept)),
+            None => TestRequest::default(),
+        };
+        OutputFormat::negotiate(&req.to_http_request())
+    }
+
+    #[test]
+    fn defaults_to_text() {
+        assert_eq!(negotiate(None), Some(OutputFormat::Text));
+        assert_eq!(negotiate(Some(" ")), Some(OutputFormat::Text));
+        assert_eq!(negotiate(Some("*/*")), Some(OutputFormat::Text));
+    }
+
+    #[test]
+    fn follows_quality_values() {
+        assert_eq!(negotiate(Some("text/csv;q=0.5, application/json")), Some(OutputFormat::Json));
+        assert_eq!(negotiate(Some("application/toml, text/yaml")), Some(OutputFormat::Toml));
+        assert_eq!(negotiate(Some("image/png, Application/X-YAML;q=0.1")), Some(OutputFormat::Yaml));
+        assert_eq!(negotiate(Some("text/csv;q=0, image/png")), None);
+    }
+
+    #[test]
+    fn serializes_structured_formats() {
+        let value = serde_json::json!({"a": 1});
+        assert_eq!(OutputFormat::Json.serialize(&value).unwrap(), r#"{"a":1}"#);
+        assert_eq!(OutputFormat::Toml.serialize(&value).unwrap(), "a = 1\n");
+        assert_eq!(OutputFormat::Yaml.serialize(&value).unwrap(), "a: 1\n");
+        assert!(OutputFormat::Csv.serialize(&value).is_err());
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use cargo_manifest::{Manifest, Package};
+use serde::{Deserialize, Serialize};
+use shuttle_runtime::__internals::serde_json::{self, Value as JsonValue};
+use toml::Value;
+use super::quantity::{ItemPolicy, DEFAULT_ITEM};
+use super::PackageMetadata;
+
+const MAGIC_KEYWORD: &str = "Christmas 2024";
+
+/// manifest 必须满足的规则，启动时从配置文件加载
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Policy {
+    /// 至少包含其中一个关键字
+    #[serde(default)]
+    keywords: Vec<String>,
+    /// 必须全部出现在 authors 中
+    #[serde(default)]
+    authors: Vec<String>,
+    min_rust_version: Option<String>,
+    /// license 必须是其中之一
+    #[serde(default)]
+    licenses: Vec<String>,
+    #[serde(default)]
+    rules: Vec<CustomRule>,
+    /// 按物品名配置数量的单位与是否允许小数
+    #[serde(default)]
+    items: BTreeMap<String, ItemPolicy>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            keywords: vec![MAGIC_KEYWORD.to_string()],
+            authors: vec![],
+            min_rust_version: None,
+            licenses: vec![],
+            rules: vec![],
+            items: BTreeMap::new(),
+        }
+    }
+}
+
+/// 对 manifest 中某个字段的自定义判断，field 为点分路径，如 "package.edition"
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct CustomRule {
+    name: String,
+    field: String,
+    message: Option<String>,
+    #[serde(flatten)]
+    check: Check,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+enum Check {
+    Exists(bool),
+    Equals(JsonValue),
+    OneOf(Vec<JsonValue>),
+    /// 字段为数组时要求包含该值，为字符串时要求包含该子串
+    Contains(JsonValue),
+}
+
+impl Check {
+    fn test(&self, value: Option<&JsonValue>) -> bool {
+        match (self, value) {
+            (Check::Exists(expected), value) => value.is_some() == *expected,
+            (_, None) => false,
+            (Check::Equals(expected), Some(v)) => v == expected,
+            (Check::OneOf(expected), Some(v)) => expected.contains(v),
+            (Check::Contains(expected), Some(JsonValue::Array(items))) => items.contains(expected),
+            (Check::Contains(JsonValue::String(expected)), Some(JsonValue::String(s))) => {
+                s.contains(expected.as_str())
+            }
+            (Check::Contains(_), Some(_)) => false,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Violation {
+    rule: String,
+    message: String,
+}
+
+impl Violation {
+    fn new(rule: &str, message: String) -> Self {
+        Self {
+            rule: rule.to_string(),
+            message,
+        }
+    }
+
+    pub(crate) fn rule(&self) -> &str {
+        &self.rule
+    }
+
+    pub(crate) fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// "1.70" 与 "1.70.0" 视为相同版本
+pub(super) fn parse_version(s: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = s.trim().split('.').map(|p| p.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    let patch = parts.next().unwrap_or(Some(0))?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor, patch))
+}
+
+fn lookup<'a>(root: &'a JsonValue, path: &str) -> Option<&'a JsonValue> {
+    path.split('.')
+        .try_fold(root, |v, key| v.get(key))
+        .filter(|v| !v.is_null())
+}
+
+impl Policy {
+    /// 文件不存在时使用默认规则（只要求 magic keyword）
+    pub(crate) fn load(path: &str) -> Self {
+        let policy: Self = match std::fs::read_to_string(path) {
+            Ok(s) => toml::from_str(&s).expect("Failed to parse manifest policy"),
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::default(),
+            Err(e) => panic!("Failed to read manifest policy: {}", e),
+        };
+        if let Some(min) = &policy.min_rust_version {
+            parse_version(min).expect("Invalid min-rust-version in manifest policy");
+        }
+        for (name, item) in &policy.items {
+            if let Err(e) = item.validate() {
+                panic!("Invalid quantity policy for {:?}: {}", name, e);
+            }
+        }
+        policy
+    }
+
+    pub(super) fn item(&self, name: &str) -> &ItemPolicy {
+        self.items.get(name).unwrap_or(&DEFAULT_ITEM)
+    }
+
+    pub(super) fn check(
+        &self,
+        pkg: &Package<PackageMetadata>,
+        manifest: &Manifest<PackageMetadata, Value>,
+    ) -> Vec<Violation> {
+        let mut violations = vec![];
+
+        if !self.keywords.is_empty() {
+            let keywords = pkg
+                .keywords
+                .as_ref()
+                .and_then(|keywords| keywords.as_ref().as_local());
+            if !keywords.is_some_and(|kws| self.keywords.iter().any(|k| kws.contains(k))) {
+                let message = if self.keywords == [MAGIC_KEYWORD] {
+                    "Magic keyword not provided".to_string()
+                } else {
+                    format!("Expected one of the keywords {:?}", self.keywords)
+                };
+                violations.push(Violation::new("keywords", message));
+            }
+        }
+
+        let authors = pkg
+            .authors
+            .as_ref()
+            .and_then(|authors| authors.as_ref().as_local());
+        for author in &self.authors {
+            if !authors.is_some_and(|a| a.contains(author)) {
+                violations.push(Violation::new(
+                    "authors",
+                    format!("Missing required author {:?}", author),
+                ));
+            }
+        }
+
+        if let Some(min) = &self.min_rust_version {
+            let actual = pkg
+                .rust_version
+                .as_ref()
+                .and_then(|v| v.as_ref().as_local())
+                .and_then(|v| parse_version(v));
+            if actual.is_none() || actual < parse_version(min) {
+                violations.push(Violation::new(
+                    "rust-version",
+                    format!("rust-version must be at least {}", min),
+                ));
+            }
+        }
+
+        if !self.licenses.is_empty() {
+            let license = pkg
+                .license
+                .as_ref()
+                .and_then(|l| l.as_ref().as_local());
+            if !license.is_some_and(|l| self.licenses.contains(l)) {
+                violations.push(Violation::new(
+                    "license",
+                    format!("license must be one of {:?}", self.licenses),
+                ));
+            }
+        }
+
+        if !self.rules.is_empty() {
+            let root = serde_json::to_value(manifest).unwrap_or_default();
+            for rule in &self.rules {
+                if !rule.check.test(lookup(&root, &rule.field)) {
+                    let message = rule
+                        .message
+                        .clone()
+                        .unwrap_or_else(|| format!("`{}` failed {:?}", rule.field, rule.check));
+                    violations.push(Violation::new(&rule.name, message));
+                }
+            }
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_manifest;
+    use super::*;
+
+    fn rules(policy: &str, manifest: &str) -> Vec<String> {
+        let policy: Policy = toml::from_str(policy).unwrap();
+        let manifest = parse_manifest(manifest, "application/toml", false).unwrap();
+        let pkg = manifest.package.as_ref().unwrap();
+        policy.check(pkg, &manifest).into_iter().map(|v| v.rule).collect()
+    }
+
+    const MANIFEST: &str = r#"
+        [package]
+        name = "gifts"
+        edition = "2021"
+        authors = ["Santa", "Rudolph"]
+        keywords = ["Christmas 2024"]
+        license = "MIT"
+        rust-version = "1.70"
+    "#;
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(parse_version("1.70"), Some((1, 70, 0)));
+        assert_eq!(parse_version(" 1.70.2 "), Some((1, 70, 2)));
+        assert_eq!(parse_version("1"), Some((1, 0, 0)));
+        assert_eq!(parse_version("1.70.0.1"), None);
+        assert_eq!(parse_version("1.x"), None);
+        assert_eq!(parse_version(""), None);
+    }
+
+    #[test]
+    fn default_policy_requires_the_magic_keyword() {
+        assert!(rules("", MANIFEST).is_empty());
+        let policy = Policy::default();
+        let manifest = parse_manifest("[package]\nname = \"x\"", "application/toml", false).unwrap();
+        let violations = policy.check(manifest.package.as_ref().unwrap(), &manifest);
+        assert_eq!(violations[0].message(), "Magic keyword not provided");
+    }
+
+    #[test]
+    fn checks_authors_versions_and_licenses() {
+        let policy = r#"
+            keywords = ["Easter"]
+            authors = ["Santa", "Grinch"]
+            min-rust-version = "1.75"
+            licenses = ["Apache-2.0"]
+        "#;
+        assert_eq!(rules(policy, MANIFEST), ["keywords", "authors", "rust-version", "license"]);
+        let policy = r#"
+            authors = ["Santa"]
+            min-rust-version = "1.70.0"
+            licenses = ["MIT"]
+        "#;
+        assert!(rules(policy, MANIFEST).is_empty());
+    }
+
+    #[test]
+    fn runs_custom_rules() {
+        let policy = r#"
+            [[rules]]
+            name = "edition"
+            field = "package.edition"
+            equals = "2021"
+
+            [[rules]]
+            name = "no-homepage"
+            field = "package.homepage"
+            exists = false
+
+            [[rules]]
+            name = "has-rudolph"
+            field = "package.authors"
+            contains = "Rudolph"
+
+            [[rules]]
+            name = "name-prefix"
+            field = "package.name"
+            contains = "toy"
+
+            [[rules]]
+            name = "license"
+            field = "package.license"
+            one-of = ["Apache-2.0", "GPL-3.0"]
+        "#;
+        assert_eq!(rules(policy, MANIFEST), ["name-prefix", "license"]);
+    }
+
+    #[test]
+    fn rejects_unknown_policy_fields() {
+        assert!(toml::from_str::<Policy>("keyword = []").is_err());
+        assert!(toml::from_str::<Policy>("[[rules]]\nname = \"x\"\nfield = \"a\"\nmatches = 1").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Add;
+use serde::{Deserialize, Serialize};
+use super::{OrderQuantity, RejectReason};
+
+/// 换算后的数量，整数时按整数输出，保持原有的响应格式
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub(super) enum Amount {
+    Whole(u64),
+    Decimal(f64),
+}
+
+impl Amount {
+    pub(super) fn from_f64(value: f64) -> Self {
+        // 去掉换算带来的浮点误差
+        let value = (value * 1e9).round() / 1e9;
+        if value.fract() == 0.0 && value >= 0.0 && value <= u64::MAX as f64 {
+            Amount::Whole(value as u64)
+        } else {
+            Amount::Decimal(value)
+        }
+    }
+
+    pub(super) fn as_f64(self) -> f64 {
+        match self {
+            Amount::Whole(v) => v as f64,
+            Amount::Decimal(v) => v,
+        }
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        match (self, other) {
+            (Amount::Whole(a), Amount::Whole(b)) => Amount::Whole(a.saturating_add(b)),
+            (a, b) => Amount::from_f64(a.as_f64() + b.as_f64()),
+        }
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Amount::Whole(v) => write!(f, "{}", v),
+            Amount::Decimal(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Count,
+    Mass,
+    Volume,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Unit<'a> {
+    dimension: Dimension,
+    /// 输出时使用的名称，计数的基本单位没有名称
+    symbol: Option<&'a str>,
+    /// 相对于该量纲基本单位（个、kg、l）的倍数
+    factor: f64,
+}
+
+const fn unit(dimension: Dimension, symbol: &'static str, factor: f64) -> Unit<'static> {
+    Unit {
+        dimension,
+        symbol: Some(symbol),
+        factor,
+    }
+}
+
+const PIECE: Unit<'static> = Unit {
+    dimension: Dimension::Count,
+    symbol: None,
+    factor: 1.0,
+};
+
+fn lookup_unit(name: &str) -> Option<Unit<'static>> {
+    let unit = match name {
+        "" | "pc" | "pcs" | "piece" | "pieces" | "unit" | "units" | "item" | "items" => PIECE,
+        "pair" | "pairs" => unit(Dimension::Count, "pair", 2.0),
+        "dozen" | "dozens" | "dz" => unit(Dimension::Count, "dozen", 12.0),
+        "gross" => unit(Dimension::Count, "gross", 144.0),
+        "mg" => unit(Dimension::Mass, "mg", 1e-6),
+        "g" | "gram" | "grams" => unit(Dimension::Mass, "g", 1e-3),
+        "kg" | "kilogram" | "kilograms" => unit(Dimension::Mass, "kg", 1.0),
+        "t" | "tonne" | "tonnes" => unit(Dimension::Mass, "t", 1e3),
+        "oz" | "ounce" | "ounces" => unit(Dimension::Mass, "oz", 0.028349523125),
+        "lb" | "lbs" | "pound" | "pounds" => unit(Dimension::Mass, "lb", 0.45359237),
+        "ml" | "millilitre" | "milliliter" | "millilitres" | "milliliters" => {
+            unit(Dimension::Volume, "ml", 1e-3)
+        }
+        "l" | "litre" | "liter" | "litres" | "liters" => unit(Dimension::Volume, "l", 1.0),
+        "gal" | "gallon" | "gallons" => unit(Dimension::Volume, "gal", 3.785411784),
+        _ => return None,
+    };
+    Some(unit)
+}
+
+/// 拆出开头的数字，支持 "-1.5"、"1e3" 这样的写法；e 后面没有数字时属于单位名
+fn split_number(s: &str) -> (&str, &str) {
+    let b = s.as_bytes();
+    let digits = |mut i: usize| {
+        while b.get(i).is_some_and(|c| c.is_ascii_digit() || *c == b'.') {
+            i += 1;
+        }
+        i
+    };
+    let mut end = digits(usize::from(matches!(b.first(), Some(b'+' | b'-'))));
+    if matches!(b.get(end), Some(b'e' | b'E')) {
+        let exp = end + 1 + usize::from(matches!(b.get(end + 1), Some(b'+' | b'-')));
+        if b.get(exp).is_some_and(u8::is_ascii_digit) {
+            end = digits(exp);
+        }
+    }
+    (s[..end].trim(), s[end..].trim())
+}
+
+/// 单个物品的数量规则，在 policy 配置的 [items."名称"] 中设置
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct ItemPolicy {
+    /// 允许换算后出现小数
+    #[serde(default)]
+    decimal: bool,
+    /// 换算到的单位，不带单位的数字也按它解释；没有配置时只接受计数单位
+    unit: Option<String>,
+    /// 物品自己的计数单位，如 box = 12
+    #[serde(default)]
+    units: BTreeMap<String, f64>,
+}
+
+pub(super) static DEFAULT_ITEM: ItemPolicy = ItemPolicy {
+    decimal: false,
+    unit: None,
+    units: BTreeMap::new(),
+};
+
+impl ItemPolicy {
+    /// 自定义单位优先，"boxes" 也能匹配 box
+    fn unit(&self, name: &str) -> Option<Unit<'_>> {
+        let name = name.to_ascii_lowercase();
+        let custom = [Some(name.as_str()), name.strip_suffix('s'), name.strip_suffix("es")]
+            .into_iter()
+            .flatten()
+            .find_map(|n| self.units.get_key_value(n));
+        match custom {
+            Some((symbol, &factor)) => Some(Unit {
+                dimension: Dimension::Count,
+                symbol: Some(symbol),
+                factor,
+            }),
+            None => lookup_unit(&name),
+        }
+    }
+
+    fn target(&self) -> Option<Unit<'_>> {
+        self.unit.as_deref().and_then(|name| self.unit(name))
+    }
+
+    /// 启动时检查配置的单位是否存在
+    pub(super) fn validate(&self) -> Result<(), String> {
+        if let Some(name) = &self.unit {
+            self.target()
+                .ok_or_else(|| format!("unknown unit {:?}", name))?;
+        }
+        match self.units.iter().find(|(_, f)| !f.is_finite() || **f <= 0.0) {
+            Some((name, _)) => Err(format!("unit {:?} must be a positive number", name)),
+            None => Ok(()),
+        }
+    }
+
+    /// 换算为目标单位，返回数量与输出用的单位名
+    pub(super) fn normalize(
+        &self,
+        quantity: &OrderQuantity,
+    ) -> Result<(Amount, Option<&str>), RejectReason> {
+        let target = self.target();
+        let (value, from) = match quantity {
+            OrderQuantity::U32(v) => (*v as f64, target.unwrap_or(PIECE)),
+            // 不允许小数的物品保持原来的判断方式
+            OrderQuantity::Other(v) if !self.decimal => {
+                return Err(if v.fract() != 0.0 || !v.is_finite() {
+                    RejectReason::NonInteger
+                } else if *v < 0.0 {
+                    RejectReason::Negative
+                } else {
+                    RejectReason::OutOfRange
+                });
+            }
+            OrderQuantity::Other(v) => (*v, target.unwrap_or(PIECE)),
+            OrderQuantity::String(s) => {
+                let (number, name) = split_number(s.trim());
+                // 不带单位的字符串仍按非整数拒绝
+                if name.is_empty() {
+                    return Err(RejectReason::NonInteger);
+                }
+                let value = number.parse::<f64>().map_err(|_| RejectReason::NonInteger)?;
+                (value, self.unit(name).ok_or(RejectReason::UnknownUnit)?)
+            }
+        };
+
+        if !value.is_finite() {
+            return Err(RejectReason::NonInteger);
+        }
+        if value < 0.0 {
+            return Err(RejectReason::Negative);
+        }
+        let to = match target {
+            Some(to) if to.dimension != from.dimension => {
+                return Err(RejectReason::IncompatibleUnit)
+            }
+            Some(to) => to,
+            // 没有配置单位的物品只接受整数个数，"3 kg"、"1.5 dozen" 都不接受
+            None if from.dimension != Dimension::Count => return Err(RejectReason::IncompatibleUnit),
+            None if value.fract() != 0.0 => return Err(RejectReason::NonInteger),
+            None => PIECE,
+        };
+
+        let amount = Amount::from_f64(value * from.factor / to.factor);
+        match amount {
+            Amount::Decimal(_) if !self.decimal => Err(RejectReason::NonInteger),
+            Amount::Whole(v) if v > u32::MAX as u64 => Err(RejectReason::OutOfRange),
+            _ => Ok((amount, to.symbol)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(toml: &str) -> ItemPolicy {
+        let policy: ItemPolicy = toml::from_str(toml).unwrap();
+        policy.validate().unwrap();
+        policy
+    }
+
+    fn text(s: &str) -> OrderQuantity {
+        OrderQuantity::String(s.to_string())
+    }
+
+    #[test]
+    fn splits_numbers_with_exponents() {
+        assert_eq!(split_number("1e3 kg"), ("1e3", "kg"));
+        assert_eq!(split_number("2.5E-1l"), ("2.5E-1", "l"));
+        assert_eq!(split_number("-3 dozen"), ("-3", "dozen"));
+        assert_eq!(split_number("2eggs"), ("2", "eggs"));
+        assert_eq!(split_number("12"), ("12", ""));
+    }
+
+    #[test]
+    fn unconfigured_items_accept_only_integer_counts() {
+        let item = &DEFAULT_ITEM;
+        assert_eq!(item.normalize(&OrderQuantity::U32(3)), Ok((Amount::Whole(3), None)));
+        assert_eq!(item.normalize(&text("2 dozen")), Ok((Amount::Whole(24), None)));
+        assert_eq!(item.normalize(&text("3 kg")), Err(RejectReason::IncompatibleUnit));
+        assert_eq!(item.normalize(&text("1e3 kg")), Err(RejectReason::IncompatibleUnit));
+        assert_eq!(item.normalize(&text("1.5 l")), Err(RejectReason::IncompatibleUnit));
+        assert_eq!(item.normalize(&text("1.5 dozen")), Err(RejectReason::NonInteger));
+        assert_eq!(item.normalize(&text("1e1 pairs")), Ok((Amount::Whole(20), None)));
+        assert_eq!(item.normalize(&OrderQuantity::Other(1.5)), Err(RejectReason::NonInteger));
+        assert_eq!(item.normalize(&text("3")), Err(RejectReason::NonInteger));
+        assert_eq!(item.normalize(&text("3 parsecs")), Err(RejectReason::UnknownUnit));
+        assert_eq!(item.normalize(&text("-1 dozen")), Err(RejectReason::Negative));
+    }
+
+    #[test]
+    fn converts_to_configured_unit() {
+        let flour = policy("unit = \"kg\"\ndecimal = true");
+        assert_eq!(flour.normalize(&text("500 g")), Ok((Amount::Decimal(0.5), Some("kg"))));
+        assert_eq!(flour.normalize(&text("1e3 g")), Ok((Amount::Whole(1), Some("kg"))));
+        assert_eq!(flour.normalize(&text("2.5e-3 t")), Ok((Amount::Decimal(2.5), Some("kg"))));
+        assert_eq!(flour.normalize(&OrderQuantity::U32(2)), Ok((Amount::Whole(2), Some("kg"))));
+        assert_eq!(flour.normalize(&text("1 l")), Err(RejectReason::IncompatibleUnit));
+
+        let milk = policy("unit = \"l\"");
+        assert_eq!(milk.normalize(&text("1500 ml")), Err(RejectReason::NonInteger));
+        assert_eq!(milk.normalize(&text("2000 ml")), Ok((Amount::Whole(2), Some("l"))));
+    }
+
+    #[test]
+    fn custom_count_units() {
+        let cars = policy("units = { box = 12 }");
+        assert_eq!(cars.normalize(&text("2 boxes")), Ok((Amount::Whole(24), None)));
+        assert_eq!(cars.normalize(&text("1 Box")), Ok((Amount::Whole(12), None)));
+        assert_eq!(cars.normalize(&text("1 kg")), Err(RejectReason::IncompatibleUnit));
+        assert!(toml::from_str::<ItemPolicy>("units = { box = -1 }").unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn amounts_add_and_display() {
+        assert_eq!(Amount::Whole(2) + Amount::Whole(3), Amount::Whole(5));
+        assert_eq!(Amount::Whole(1) + Amount::Decimal(0.5), Amount::Decimal(1.5));
+        assert_eq!(Amount::Decimal(0.1) + Amount::Decimal(0.2), Amount::Decimal(0.3));
+        assert_eq!(Amount::from_f64(0.5 + 0.5).to_string(), "1");
+    }
+}
@@ -0,0 +1,154 @@
+use serde::Serialize;
+use super::negotiate::{self, OutputFormat};
+use super::policy::Policy;
+use super::quantity::Amount;
+use super::{OrderItem, OrderQuantity, RejectReason};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Accepted,
+    Rejected,
+}
+
+#[derive(Serialize)]
+struct OrderReport<'a> {
+    index: usize,
+    item: Option<&'a str>,
+    quantity: Option<&'a OrderQuantity>,
+    /// 换算后的数量与单位，只有接受的订单才有
+    #[serde(skip_serializing_if = "Option::is_none")]
+    normalized: Option<Amount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<RejectReason>,
+}
+
+#[derive(Serialize, Default)]
+struct Totals {
+    orders: usize,
+    accepted: usize,
+    rejected: usize,
+    /// 已接受且没有单位的订单的数量之和
+    quantity: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Report<'a> {
+    orders: Vec<OrderReport<'a>>,
+    totals: Totals,
+}
+
+impl<'a> Report<'a> {
+    pub(crate) fn new(items: &'a [OrderItem], policy: &Policy) -> Self {
+        let mut totals = Totals::default();
+        let orders = items
+            .iter()
+            .enumerate()
+            .map(|(index, order)| {
+                totals.orders += 1;
+                let (status, reason, normalized, unit) = match order.validate(policy) {
+                    Ok(accepted) => {
+                        totals.accepted += 1;
+                        if let (Amount::Whole(v), None) = (accepted.quantity, &accepted.unit) {
+                            totals.quantity += v;
+                        }
+                        (Status::Accepted, None, Some(accepted.quantity), accepted.unit)
+                    }
+                    Err(reason) => {
+                        totals.rejected += 1;
+                        (Status::Rejected, Some(reason), None, None)
+                    }
+                };
+                OrderReport {
+                    index,
+                    item: order.item.as_deref(),
+                    quantity: order.quantity.as_ref(),
+                    normalized,
+                    unit,
+                    status,
+                    reason,
+                }
+            })
+            .collect();
+
+        Self { orders, totals }
+    }
+
+    pub(crate) fn render(&self, format: OutputFormat) -> Result<String, String> {
+        if format != OutputFormat::Csv {
+            return format.serialize(self);
+        }
+
+        // CSV 每行一条订单，合计不单独输出
+        let rows = self.orders.iter().map(|o| {
+            let quantity = match o.quantity {
+                Some(OrderQuantity::U32(v)) => v.to_string(),
+                Some(OrderQuantity::String(v)) => v.clone(),
+                Some(OrderQuantity::Other(v)) => v.to_string(),
+                None => String::new(),
+            };
+            format!(
+                "{},{},{},{},{},{},{}",
+                o.index,
+                negotiate::csv_field(o.item.unwrap_or_default()),
+                negotiate::csv_field(&quantity),
+                o.normalized.map(|v| v.to_string()).unwrap_or_default(),
+                negotiate::csv_field(o.unit.as_deref().unwrap_or_default()),
+                if o.status == Status::Accepted { "accepted" } else { "rejected" },
+                o.reason.map(|r| r.as_str()).unwrap_or_default()
+            )
+        });
+
+        Ok(std::iter::once("index,item,quantity,normalized,unit,status,reason".to_string())
+            .chain(rows)
+            .map(|line| line + "\r\n")
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shuttle_runtime::__internals::serde_json;
+    use super::*;
+
+    fn items(orders: &str) -> Vec<OrderItem> {
+        let metadata: super::super::PackageMetadata = toml::from_str(orders).unwrap();
+        metadata.orders.unwrap()
+    }
+
+    const ORDERS: &str = r#"
+        orders = [
+            { item = "Toy car", quantity = 2 },
+            { item = "Doll, large", quantity = "2 dozen" },
+            { quantity = 4 },
+            { item = "Lego", quantity = -1 },
+        ]
+    "#;
+
+    #[test]
+    fn counts_accepted_and_rejected_orders() {
+        let items = items(ORDERS);
+        let report = Report::new(&items, &Policy::default());
+        let json: serde_json::Value = serde_json::from_str(&report.render(OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["totals"], serde_json::json!({"orders": 4, "accepted": 2, "rejected": 2, "quantity": 26}));
+        assert_eq!(json["orders"][0]["status"], "accepted");
+        assert_eq!(json["orders"][0]["normalized"], 2);
+        assert_eq!(json["orders"][2]["reason"], "missing_item");
+        assert!(json["orders"][2].get("normalized").is_none());
+    }
+
+    #[test]
+    fn renders_one_csv_row_per_order() {
+        let items = items(ORDERS);
+        let csv = Report::new(&items, &Policy::default()).render(OutputFormat::Csv).unwrap();
+        let rows: Vec<&str> = csv.split_terminator("\r\n").collect();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], "index,item,quantity,normalized,unit,status,reason");
+        assert_eq!(rows[1], "0,Toy car,2,2,,accepted,");
+        assert_eq!(rows[2], "1,\"Doll, large\",2 dozen,24,,accepted,");
+        assert!(rows[3].starts_with("2,,4,,,rejected,"));
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use actix_web::web::Query;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use sqlx::FromRow;
+use super::batch::structured_format;
+use super::policy::Policy;
+use super::quantity::Amount;
+use super::{
+    accepted_orders, invalid, parse_manifest, reject, respond, workspace, ManifestParams, ParseError,
+};
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+/// URL 路径段中需要转义的字符，包括 `/` 和 `%` 本身
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Serialize, FromRow)]
+struct StoredOrder {
+    #[serde(skip)]
+    manifest_id: Uuid,
+    item: String,
+    quantity: f64,
+    unit: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StoredManifest {
+    id: Uuid,
+    package: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    created_at: DateTime<Utc>,
+    orders: Vec<OrderView>,
+}
+
+/// 整数数量按整数输出
+#[derive(Serialize)]
+struct OrderView {
+    item: String,
+    quantity: Amount,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+}
+
+impl From<StoredOrder> for OrderView {
+    fn from(order: StoredOrder) -> Self {
+        Self {
+            item: order.item,
+            quantity: Amount::from_f64(order.quantity),
+            unit: order.unit,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct ManifestRow {
+    id: Uuid,
+    package: String,
+    version: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+fn server_error(e: sqlx::Error) -> HttpResponse {
+    eprintln!("Database error: {}", e);
+    HttpResponse::InternalServerError().finish()
+}
+
+/// 与 /5/manifest 相同的检查，通过后把 manifest 与订单写入数据库；
+/// 没有 [package] 的 manifest 无法按包名查询，返回 400
+pub(super) async fn store(
+    body: String,
+    req: HttpRequest,
+    params: Query<ManifestParams>,
+    policy: web::Data<Arc<Policy>>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let Some(format) = structured_format(&req) else {
+        return HttpResponse::NotAcceptable().finish();
+    };
+
+    let mut content = match parse_manifest(&body, req.content_type(), params.sniff) {
+        Ok(content) => content,
+        Err(ParseError::UnsupportedMediaType) => {
+            return HttpResponse::UnsupportedMediaType().finish()
+        }
+        Err(ParseError::Invalid(diagnostic)) => return invalid(format, &diagnostic),
+    };
+    workspace::resolve(&mut content, None);
+
+    let Some(pkg) = &content.package else {
+        return HttpResponse::BadRequest().body("Manifest has no [package]");
+    };
+    let violations = policy.check(pkg, &content);
+    if !violations.is_empty() {
+        return reject(format, &violations);
+    }
+
+    let id = Uuid::new_v4();
+    let version = pkg
+        .version
+        .as_ref()
+        .and_then(|v| v.as_ref().as_local())
+        .cloned();
+    let orders = accepted_orders(pkg.metadata.as_ref(), &policy);
+
+    let result: Result<DateTime<Utc>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let created_at = sqlx::query_scalar!(
+            "INSERT INTO manifests
+        (id, package, version, content_type, body)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING created_at;",
+            id,
+            pkg.name,
+            version,
+            req.content_type(),
+            body
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for (position, order) in orders.iter().enumerate() {
+            sqlx::query!(
+                "INSERT INTO manifest_orders
+            (manifest_id, position, item, quantity, unit)
+            VALUES ($1, $2, $3, $4, $5);",
+                id,
+                position as i32,
+                order.item,
+                order.quantity.as_f64(),
+                order.unit
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(created_at)
+    }
+    .await;
+
+    let created_at = match result {
+        Ok(created_at) => created_at,
+        Err(e) => return server_error(e),
+    };
+
+    let stored = StoredManifest {
+        id,
+        package: pkg.name.clone(),
+        version,
+        created_at,
+        orders: orders
+            .into_iter()
+            .map(|o| OrderView {
+                item: o.item,
+                quantity: o.quantity,
+                unit: o.unit,
+            })
+            .collect(),
+    };
+    match format.serialize(&stored) {
+        Ok(body) => HttpResponse::Created()
+            .content_type(format.content_type())
+            .insert_header((
+                "Location",
+                format!("/5/packages/{}", utf8_percent_encode(&pkg.name, PATH_SEGMENT)),
+            ))
+            .body(body),
+        Err(e) => respond(format, Err(e)),
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct ListParams {
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: i64,
+}
+
+/// 某个包保存过的 manifest，最新的在前
+pub(super) async fn list(
+    name: web::Path<String>,
+    req: HttpRequest,
+    params: Query<ListParams>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let Some(format) = structured_format(&req) else {
+        return HttpResponse::NotAcceptable().finish();
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = params.offset.max(0);
+
+    let rows = match sqlx::query_as!(
+        ManifestRow,
+        "SELECT id, package, version, created_at FROM manifests
+        WHERE package = $1
+        ORDER BY created_at DESC, id
+        LIMIT $2 OFFSET $3",
+        name.as_str(),
+        limit,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return server_error(e),
+    };
+
+    let ids: Vec<Uuid> = rows.iter().map(|r| r.id).collect();
+    let orders = match sqlx::query_as!(
+        StoredOrder,
+        "SELECT manifest_id, item, quantity, unit FROM manifest_orders
+        WHERE manifest_id = ANY($1)
+        ORDER BY manifest_id, position",
+        &ids
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    {
+        Ok(orders) => orders,
+        Err(e) => return server_error(e),
+    };
+
+    let mut by_manifest: HashMap<Uuid, Vec<OrderView>> = HashMap::new();
+    for order in orders {
+        by_manifest
+            .entry(order.manifest_id)
+            .or_default()
+            .push(order.into());
+    }
+
+    #[derive(Serialize)]
+    struct Manifests {
+        manifests: Vec<StoredManifest>,
+    }
+    let manifests = rows
+        .into_iter()
+        .map(|row| StoredManifest {
+            orders: by_manifest.remove(&row.id).unwrap_or_default(),
+            id: row.id,
+            package: row.package,
+            version: row.version,
+            created_at: row.created_at,
+        })
+        .collect();
+    respond(format, format.serialize(&Manifests { manifests }))
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Bucket {
+    Hour,
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+impl Bucket {
+    /// date_trunc 的精度参数
+    fn as_str(&self) -> &'static str {
+        match self {
+            Bucket::Hour => "hour",
+            Bucket::Day => "day",
+            Bucket::Week => "week",
+            Bucket::Month => "month",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct TotalsParams {
+    item: Option<String>,
+    #[serde(default)]
+    bucket: Bucket,
+    /// RFC 3339，包含
+    from: Option<DateTime<Utc>>,
+    /// RFC 3339，不包含
+    to: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct Total {
+    item: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    period: DateTime<Utc>,
+    quantity: Amount,
+}
+
+/// 每个时间段内各物品（按单位分开）的订单数量之和
+pub(super) async fn totals(
+    req: HttpRequest,
+    params: Query<TotalsParams>,
+    pool: web::Data<sqlx::PgPool>,
+) -> HttpResponse {
+    let Some(format) = structured_format(&req) else {
+        return HttpResponse::NotAcceptable().finish();
+    };
+
+    let rows = match sqlx::query!(
+        r#"SELECT o.item, o.unit,
+            date_trunc($1, m.created_at) AS "period!",
+            SUM(o.quantity) AS "quantity!"
+        FROM manifest_orders o JOIN manifests m ON m.id = o.manifest_id
+        WHERE ($2::TEXT IS NULL OR o.item = $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR m.created_at >= $3)
+            AND ($4::TIMESTAMPTZ IS NULL OR m.created_at < $4)
+        GROUP BY 3, o.item, o.unit
+        ORDER BY 3, o.item, o.unit"#,
+        params.bucket.as_str(),
+        params.item,
+        params.from,
+        params.to
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return server_error(e),
+    };
+
+    #[derive(Serialize)]
+    struct Totals {
+        totals: Vec<Total>,
+    }
+    let totals = rows
+        .into_iter()
+        .map(|row| Total {
+            item: row.item,
+            unit: row.unit,
+            period: row.period,
+            quantity: Amount::from_f64(row.quantity),
+        })
+        .collect();
+    respond(format, format.serialize(&Totals { totals }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+    use shuttle_runtime::__internals::serde_json::{self, Value as JsonValue};
+    use super::*;
+
+    const MANIFEST: &str = r#"
+        [package]
+        name = "gifts"
+        version = "0.1.0"
+        keywords = ["Christmas 2024"]
+
+        [[package.metadata.orders]]
+        item = "Toy car"
+        quantity = 2
+
+        [[package.metadata.orders]]
+        item = "Doll"
+        quantity = 1.5
+    "#;
+
+    macro_rules! app {
+        ($pool:expr) => {
+            init_service(
+                App::new()
+                    .app_data(web::Data::new($pool))
+                    .app_data(web::Data::new(Arc::new(Policy::default())))
+                    .route("/store", web::post().to(store))
+                    .route("/packages/{name}", web::get().to(list))
+                    .route("/totals", web::get().to(totals)),
+            )
+            .await
+        };
+    }
+
+    fn post(body: &str) -> TestRequest {
+        TestRequest::post()
+            .uri("/store")
+            .insert_header(("Content-Type", "application/toml"))
+            .set_payload(body.to_string())
+    }
+
+    #[sqlx::test]
+    async fn stores_and_lists_manifests(pool: sqlx::PgPool) {
+        let app = app!(pool);
+        let resp = call_service(&app, post(MANIFEST).to_request()).await;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(resp.headers().get("Location").unwrap(), "/5/packages/gifts");
+        let stored: JsonValue = serde_json::from_slice(&read_body(resp).await).unwrap();
+        assert_eq!(stored["orders"], serde_json::json!([{"item": "Toy car", "quantity": 2}]));
+
+        call_service(&app, post(&MANIFEST.replace("0.1.0", "0.2.0")).to_request()).await;
+        let resp = call_service(&app, TestRequest::get().uri("/packages/gifts?limit=1").to_request()).await;
+        let listed: JsonValue = serde_json::from_slice(&read_body(resp).await).unwrap();
+        let manifests = listed["manifests"].as_array().unwrap();
+        assert_eq!(manifests.len(), 1);
+        assert_eq!(manifests[0]["version"], "0.2.0");
+        assert_eq!(manifests[0]["orders"][0]["quantity"], 2);
+
+        let resp = call_service(&app, TestRequest::get().uri("/totals?item=Toy%20car").to_request()).await;
+        let totals: JsonValue = serde_json::from_slice(&read_body(resp).await).unwrap();
+        assert_eq!(totals["totals"][0]["quantity"], 4);
+        assert_eq!(totals["totals"].as_array().unwrap().len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn escapes_the_location_header(pool: sqlx::PgPool) {
+        let app = app!(pool);
+        let manifest = MANIFEST.replace("\"gifts\"", "\"my gifts/ü?#%\"");
+        let resp = call_service(&app, post(&manifest).to_request()).await;
+        assert_eq!(resp.status(), 201);
+        let location = resp.headers().get("Location").unwrap().to_str().unwrap().to_string();
+        assert_eq!(location, "/5/packages/my%20gifts%2F%C3%BC%3F%23%25");
+
+        // 测试中没有挂在 /5 下
+        let uri = location.strip_prefix("/5").unwrap();
+        let resp = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+        let listed: JsonValue = serde_json::from_slice(&read_body(resp).await).unwrap();
+        assert_eq!(listed["manifests"][0]["package"], "my gifts/ü?#%");
+    }
+
+    #[sqlx::test]
+    async fn rejects_manifests_that_fail_the_policy(pool: sqlx::PgPool) {
+        let app = app!(pool.clone());
+        let resp = call_service(&app, post(&MANIFEST.replace("Christmas", "Easter")).to_request()).await;
+        assert_eq!(resp.status(), 400);
+        let resp = call_service(&app, post("[workspace]\nmembers = []").to_request()).await;
+        assert_eq!(resp.status(), 400);
+        let req = post(MANIFEST).insert_header(("Accept", "text/csv")).to_request();
+        assert_eq!(call_service(&app, req).await.status(), 406);
+
+        let count = sqlx::query_scalar!("SELECT COUNT(*) FROM manifests").fetch_one(&pool).await.unwrap();
+        assert_eq!(count, Some(0));
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+use actix_multipart::Multipart;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use cargo_manifest::{Manifest, MaybeInherited, WorkspacePackage};
+use flate2::read::GzDecoder;
+use futures::StreamExt;
+use toml::Value;
+use super::batch::{self, BatchResult, ManifestResult};
+use super::policy::Policy;
+use super::{respond, PackageMetadata};
+
+/// 上传的 workspace 解压后最多读取的 Cargo.toml 数量
+const MAX_MANIFESTS: usize = 256;
+/// 解压后整个 tar 的大小上限，防止压缩炸弹
+const MAX_UNPACKED: u64 = 64 << 20;
+/// workspace.members 与 exclude 合计的模式数量，以及单个模式的长度上限
+const MAX_PATTERNS: usize = 1024;
+const MAX_PATTERN_LEN: usize = 1024;
+
+/// 把 `{key}.workspace = true` 替换为 workspace.package 中的值；
+/// workspace 没有定义该字段时保持原样
+macro_rules! inherit {
+    ($pkg:expr, $ws:expr, $($field:ident),+ $(,)?) => {
+        $(
+            if matches!($pkg.$field, Some(MaybeInherited::Inherited { .. })) {
+                if let Some(v) = &$ws.$field {
+                    $pkg.$field = Some(MaybeInherited::Local(v.clone()));
+                }
+            }
+        )+
+    };
+}
+
+/// root 为 None 时使用 manifest 自身的 [workspace.package]
+pub(super) fn resolve(
+    content: &mut Manifest<PackageMetadata, Value>,
+    root: Option<&WorkspacePackage>,
+) {
+    let Some(ws) = root
+        .cloned()
+        .or_else(|| content.workspace.as_ref().and_then(|w| w.package.clone()))
+    else {
+        return;
+    };
+    let Some(pkg) = content.package.as_mut() else {
+        return;
+    };
+
+    inherit!(
+        pkg,
+        ws,
+        edition,
+        version,
+        authors,
+        description,
+        homepage,
+        documentation,
+        readme,
+        keywords,
+        categories,
+        license,
+        license_file,
+        repository,
+        rust_version,
+        exclude,
+        include,
+        publish,
+    );
+}
+
+/// 只支持单段内的 `*` 与 `?`，与 workspace.members 的常见写法一致
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    pattern.len() == path.len()
+        && pattern
+            .iter()
+            .zip(&path)
+            .all(|(p, s)| wildcard(p.as_bytes(), s.as_bytes()))
+}
+
+/// 双指针匹配，不递归；遇到不匹配时只回到最近的 `*`，最坏 O(len(p) * len(s))
+fn wildcard(p: &[u8], s: &[u8]) -> bool {
+    let (mut pi, mut si) = (0, 0);
+    // 最近一个 `*` 的位置，以及它当前匹配到 s 的哪里
+    let mut star = None;
+    while si < s.len() {
+        match p.get(pi) {
+            Some(b'*') => {
+                star = Some((pi, si));
+                pi += 1;
+            }
+            Some(&c) if c == b'?' || c == s[si] => {
+                pi += 1;
+                si += 1;
+            }
+            _ => match star {
+                Some((sp, ss)) => {
+                    star = Some((sp, ss + 1));
+                    pi = sp + 1;
+                    si = ss + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+fn normalize(path: &str) -> String {
+    path.trim_start_matches("./").trim_start_matches('/').to_string()
+}
+
+fn is_manifest_path(path: &str) -> bool {
+    path == "Cargo.toml" || path.ends_with("/Cargo.toml")
+}
+
+/// 目录部分，根目录为空字符串
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// 找到 workspace 根目录的 Cargo.toml，按成员列表逐个检查
+fn evaluate(files: &BTreeMap<String, String>, policy: &Policy) -> Result<Vec<ManifestResult>, String> {
+    let (root_path, root) = files
+        .iter()
+        .filter_map(|(path, body)| {
+            toml::from_str::<Manifest<PackageMetadata, Value>>(body)
+                .ok()
+                .filter(|m| m.workspace.is_some())
+                .map(|m| (path, m))
+        })
+        .min_by_key(|(path, _)| path.matches('/').count())
+        .ok_or_else(|| "No workspace root Cargo.toml found".to_string())?;
+
+    let workspace = root.workspace.as_ref().expect("filtered above");
+    let root_dir = parent(root_path);
+    let excludes = workspace.exclude.clone().unwrap_or_default();
+    if workspace.members.len() + excludes.len() > MAX_PATTERNS {
+        return Err(format!("Workspace has more than {} member patterns", MAX_PATTERNS));
+    }
+    if let Some(long) = workspace.members.iter().chain(&excludes).find(|p| p.len() > MAX_PATTERN_LEN) {
+        let head: String = long.chars().take(32).collect();
+        return Err(format!("Member pattern {:?}... is longer than {} bytes", head, MAX_PATTERN_LEN));
+    }
+
+    let mut results = vec![];
+    for (path, body) in files {
+        let dir = parent(path);
+        let relative = if path == root_path {
+            ""
+        } else if root_dir.is_empty() {
+            dir
+        } else {
+            match dir.strip_prefix(root_dir).and_then(|d| d.strip_prefix('/')) {
+                Some(relative) => relative,
+                None => continue,
+            }
+        };
+
+        let is_member = path == root_path
+            || (workspace.members.iter().any(|m| glob_match(m, relative))
+                && !excludes.iter().any(|e| glob_match(e, relative)));
+        if !is_member {
+            continue;
+        }
+        // 虚拟 workspace 的根 manifest 没有 [package]，不算成员
+        if path == root_path && root.package.is_none() {
+            continue;
+        }
+
+        results.push(batch::check(
+            path.clone(),
+            body,
+            "application/toml",
+            workspace.package.as_ref(),
+            policy,
+        ));
+    }
+    Ok(results)
+}
+
+fn too_large(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} is too large", what))
+}
+
+fn read_tarball(bytes: &[u8]) -> std::io::Result<BTreeMap<String, String>> {
+    // gzip 魔数
+    let reader: Box<dyn Read + '_> = if bytes.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(bytes))
+    } else {
+        Box::new(bytes)
+    };
+    // 多读一个字节用来判断是否超出上限；截断后 tar 报的错误没有意义，以上限为准
+    let mut archive = tar::Archive::new(reader.take(MAX_UNPACKED + 1));
+    let files = read_manifests(&mut archive);
+    if archive.into_inner().limit() == 0 {
+        return Err(too_large("Unpacked archive"));
+    }
+    files
+}
+
+fn read_manifests(archive: &mut tar::Archive<impl Read>) -> std::io::Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = normalize(&entry.path()?.to_string_lossy());
+        if !is_manifest_path(&path) {
+            continue;
+        }
+        if entry.size() > batch::MAX_PART as u64 {
+            return Err(too_large(&path));
+        }
+        let mut body = String::new();
+        entry.by_ref().take(batch::MAX_PART as u64).read_to_string(&mut body)?;
+        files.insert(path, body);
+        if files.len() >= MAX_MANIFESTS {
+            break;
+        }
+    }
+    Ok(files)
+}
+
+/// 接受 multipart（filename 为文件在 workspace 中的路径）或 tar / tar.gz 包
+pub(super) async fn workspace(
+    req: HttpRequest,
+    mut payload: web::Payload,
+    policy: web::Data<Arc<Policy>>,
+) -> HttpResponse {
+    let Some(format) = batch::structured_format(&req) else {
+        return HttpResponse::NotAcceptable().finish();
+    };
+
+    let files = match req.content_type() {
+        "multipart/form-data" => {
+            match batch::read_parts(Multipart::new(req.headers(), payload)).await {
+                Ok(parts) => parts
+                    .into_iter()
+                    .filter_map(|part| {
+                        let path = normalize(&part.name);
+                        let body = String::from_utf8(part.bytes).ok()?;
+                        is_manifest_path(&path).then_some((path, body))
+                    })
+                    .take(MAX_MANIFESTS)
+                    .collect(),
+                Err(e) => return e.response("workspace"),
+            }
+        }
+        "application/x-tar" | "application/gzip" | "application/x-gzip" => {
+            let mut bytes = web::BytesMut::new();
+            while let Some(chunk) = payload.next().await {
+                match chunk {
+                    Ok(chunk) if bytes.len() + chunk.len() > batch::MAX_PAYLOAD => {
+                        return HttpResponse::PayloadTooLarge().finish()
+                    }
+                    Ok(chunk) => bytes.extend_from_slice(&chunk),
+                    Err(_) => return HttpResponse::BadRequest().finish(),
+                }
+            }
+            match read_tarball(&bytes) {
+                Ok(files) => files,
+                Err(e) => return HttpResponse::BadRequest().body(format!("Invalid archive: {}", e)),
+            }
+        }
+        _ => return HttpResponse::UnsupportedMediaType().finish(),
+    };
+
+    match evaluate(&files, &policy) {
+        Ok(members) => respond(format, format.serialize(&BatchResult::new(members))),
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use super::*;
+
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, body) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(body.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *body).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn reads_manifests_from_tar_and_gzip() {
+        let tar = tarball(&[
+            ("./Cargo.toml", b"[workspace]\nmembers = [\"a\"]\n"),
+            ("a/Cargo.toml", b"[package]\nname = \"a\"\n"),
+            ("a/src/lib.rs", b""),
+        ]);
+        for bytes in [tar.clone(), gzip(&tar)] {
+            let files = read_tarball(&bytes).unwrap();
+            assert_eq!(files.keys().collect::<Vec<_>>(), ["Cargo.toml", "a/Cargo.toml"]);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_manifest() {
+        let body = vec![b'#'; batch::MAX_PART + 1];
+        let err = read_tarball(&tarball(&[("Cargo.toml", &body)])).unwrap_err();
+        assert_eq!(err.to_string(), "Cargo.toml is too large");
+    }
+
+    #[test]
+    fn rejects_gzip_bomb() {
+        // 压缩后只有几十 KB，解压后超过 MAX_UNPACKED
+        let padding = vec![0u8; MAX_UNPACKED as usize];
+        let bomb = gzip(&tarball(&[("Cargo.toml", b"[workspace]\n"), ("padding", &padding)]));
+        assert!(bomb.len() < batch::MAX_PAYLOAD);
+        let err = read_tarball(&bomb).unwrap_err();
+        assert_eq!(err.to_string(), "Unpacked archive is too large");
+    }
+
+    #[test]
+    fn matches_member_globs() {
+        assert!(glob_match("crates/*", "crates/core"));
+        assert!(!glob_match("crates/*", "crates/core/sub"));
+        assert!(glob_match("tool?", "tools"));
+        assert!(glob_match("*-cli", "foo-cli"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("**", ""));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn long_star_patterns_do_not_recurse() {
+        let pattern = "*".repeat(100_000);
+        assert!(wildcard(pattern.as_bytes(), b"crates"));
+        assert!(wildcard(pattern.as_bytes(), b""));
+    }
+
+    #[test]
+    fn pathological_patterns_finish_quickly() {
+        let pattern = "*a".repeat(50) + "b";
+        let path = "a".repeat(200);
+        let start = std::time::Instant::now();
+        assert!(!wildcard(pattern.as_bytes(), path.as_bytes()));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn caps_member_patterns() {
+        let files = |members: &str| {
+            let root = format!("[workspace]\nmembers = {}\n", members);
+            BTreeMap::from([("Cargo.toml".to_string(), root)])
+        };
+        let long = format!("[{:?}]", "*".repeat(MAX_PATTERN_LEN + 1));
+        assert!(evaluate(&files(&long), &Policy::default()).err().unwrap().contains("longer than"));
+        let many = format!("[{}]", vec!["\"a\""; MAX_PATTERNS + 1].join(","));
+        assert!(evaluate(&files(&many), &Policy::default()).err().unwrap().contains("more than"));
+        assert!(evaluate(&files("[\"a\"]"), &Policy::default()).unwrap().is_empty());
+    }
+}
@@ -1,118 +1,378 @@
 use std::sync::Arc;
-use std::time::Duration;
+use actix_web::http::header::{ContentType, HeaderName, HeaderValue};
+use actix_web::web::Query;
 use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
-use shuttle_runtime::__internals::serde_json;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
+use shuttle_runtime::__internals::serde_json::{self, value::RawValue};
+use super::rate_limit::{client_key, RateLimit, RateLimiter};
+use exact::{Ratio, Rounding};
+use inventory::{Outcome, Tank};
+use units::Dimension;
 
-pub struct TokenBucket {
-    capacity: u32,
-    tokens: Mutex<u32>,
+mod exact;
+mod inventory;
+mod units;
+
+/// 原来的四个字段，只能填一个，liters 与 gallons、litres 与 pints 互相换算
+#[derive(Deserialize, Debug)]
+struct VolumeUnit {
+    liters: Option<Ratio>,
+    gallons: Option<Ratio>,
+    litres: Option<Ratio>,
+    pints: Option<Ratio>,
 }
 
-impl TokenBucket {
-    pub fn new(capacity: u32) -> Self {
-        Self {
-            capacity,
-            tokens: Mutex::new(capacity),
+impl VolumeUnit {
+    /// 唯一填写的字段名和值，字段名同时也是单位名
+    fn single(&self) -> Option<(&'static str, &Ratio)> {
+        let fields = [
+            ("liters", &self.liters),
+            ("gallons", &self.gallons),
+            ("litres", &self.litres),
+            ("pints", &self.pints),
+        ];
+        let mut set = fields.into_iter().filter_map(|(name, v)| Some((name, v.as_ref()?)));
+        match (set.next(), set.next()) {
+            (Some(field), None) => Some(field),
+            _ => None,
         }
     }
 
-    pub async fn replenish(&self) {
-        loop {
-            sleep(Duration::from_secs(1)).await;
-            let mut tokens = self.tokens.lock().await;
-            if *tokens < self.capacity {
-                *tokens += 1;
-            }
+    fn counterpart(field: &str) -> &'static str {
+        match field {
+            "liters" => "gallons",
+            "gallons" => "liters",
+            "litres" => "pints",
+            _ => "litres",
         }
     }
+}
 
-    pub async fn consume(&self) -> bool {
-        let mut tokens = self.tokens.lock().await;
-        if *tokens > 0 {
-            *tokens -= 1;
-            true
-        } else {
-            false
-        }
+/// 任意两个同量纲单位之间的换算，如 {"value": 5, "from": "lb", "to": "kg"}；
+/// value 可以是字符串，如 "0.1" 或 "1/3"，按精确值计算
+#[derive(Deserialize, Debug)]
+struct Conversion {
+    value: Ratio,
+    from: String,
+    to: String,
+}
+
+#[derive(Debug)]
+enum MilkRequest {
+    Convert(Conversion),
+    Legacy(VolumeUnit),
+}
+
+/// 相当于 untagged，但 untagged 会先把数字缓存成 f64，Ratio 就拿不到原文了
+impl<'de> Deserialize<'de> for MilkRequest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = <Box<RawValue>>::deserialize(deserializer)?;
+        serde_json::from_str(raw.get())
+            .map(MilkRequest::Convert)
+            .or_else(|_| serde_json::from_str(raw.get()).map(MilkRequest::Legacy))
+            .map_err(serde::de::Error::custom)
     }
+}
 
-    pub async fn refill(&self) {
-        let mut tokens = self.tokens.lock().await;
-        *tokens = self.capacity;
+impl MilkRequest {
+    /// 请求的体积换算成升，不是体积时为 None
+    fn liters(&self) -> Option<Ratio> {
+        let (value, unit) = match self {
+            MilkRequest::Convert(c) => (&c.value, units::lookup(&c.from)?),
+            MilkRequest::Legacy(v) => {
+                let (field, value) = v.single()?;
+                (value, units::lookup(field)?)
+            }
+        };
+        (unit.dimension == Dimension::Volume).then(|| unit.to_base(value))
     }
 }
 
-#[derive(Deserialize, Debug, Serialize)]
-struct VolumeUnit {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    liters: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    gallons: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    litres: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pints: Option<f32>,
+/// 结果不能写成有限小数、又没有指定 precision 时保留的小数位数
+const DEFAULT_PRECISION: u32 = 20;
+const MAX_PRECISION: u32 = 100;
+
+#[derive(Deserialize, Debug)]
+struct MilkParams {
+    /// 最多保留的小数位数，能精确表示时不会补 0
+    precision: Option<u32>,
+    #[serde(default)]
+    rounding: Rounding,
+}
+
+impl MilkParams {
+    fn format(&self, value: &Ratio) -> String {
+        let places = self
+            .precision
+            .unwrap_or_else(|| value.terminating_places().unwrap_or(DEFAULT_PRECISION));
+        value.to_decimal(places, self.rounding)
+    }
+}
+
+/// 每升消耗一个令牌，向上取整；不是 JSON 或不是体积的请求消耗 1 个
+fn milk_cost(req: &HttpRequest, body: &[u8]) -> u32 {
+    if req.content_type() != "application/json" {
+        return 1;
+    }
+    serde_json::from_slice::<MilkRequest>(body)
+        .ok()
+        .and_then(|r| r.liters())
+        .map_or(1, |l| l.abs().ceil_u32().unwrap_or(u32::MAX).max(1))
+}
+
+/// 返回 {"key": value}。value 直接写入，不经过 f64，保留全部位数
+fn number_response(key: &str, value: &str) -> HttpResponse {
+    let key = serde_json::to_string(key).expect("string serializes");
+    HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(format!("{{{}:{}}}", key, value))
+}
+
+/// 换算结果，返回 (键, 数值)；键与原来的字段一样是目标单位，新格式中用单位的规范名称
+fn convert(request: &MilkRequest, params: &MilkParams) -> Result<(String, String), HttpResponse> {
+    match request {
+        MilkRequest::Convert(Conversion { value, from, to }) => match units::convert(value, from, to) {
+            Ok((result, name)) => Ok((name.to_string(), params.format(&result))),
+            Err(e) => Err(HttpResponse::BadRequest().body(format!("{}\n", e))),
+        },
+        MilkRequest::Legacy(v) => {
+            let Some((field, value)) = v.single() else {
+                return Err(HttpResponse::BadRequest().finish());
+            };
+            let target = VolumeUnit::counterpart(field);
+            match units::convert(value, field, target) {
+                Ok((result, _)) => Ok((target.to_string(), params.format(&result))),
+                Err(_) => Err(HttpResponse::BadRequest().finish()),
+            }
+        }
+    }
 }
 
+#[derive(Serialize)]
+struct Insufficient {
+    error: &'static str,
+    /// 升
+    requested: f64,
+    available: f64,
+}
 
-async fn milk(body: String, req: HttpRequest, bucket: actix_web::web::Data<Arc<TokenBucket>>) -> impl Responder {
-    if !bucket.consume().await {
-        return HttpResponse::TooManyRequests().body("No milk available\n")
+/// 库存不足，JSON 请求返回请求量和剩余量
+fn insufficient(json: bool, requested: &Ratio, tank: &Tank) -> HttpResponse {
+    if !json {
+        return HttpResponse::Conflict().body("Not enough milk in the tank\n");
     }
+    HttpResponse::Conflict().json(Insufficient {
+        error: "Not enough milk in the tank",
+        requested: inventory::to_f64(requested),
+        available: tank.stock,
+    })
+}
 
+/// 限流由 scope 中的 RateLimit 负责。请求的体积从奶罐中扣除，
+/// 不是 JSON 的请求取出 1 升，其他量纲的换算不动库存
+async fn milk(
+    body: String,
+    req: HttpRequest,
+    params: Query<MilkParams>,
+    pool: web::Data<sqlx::PgPool>,
+) -> impl Responder {
     // print body
     println!("{}", body);
 
-    let content: Result<VolumeUnit, String> = match req.content_type() {
-        "application/json" => serde_json::from_str(&body).map_err(|e| format!("JSON parsing error: {}", e)),
-        _ => return HttpResponse::Ok().body("Milk withdrawn\n"),
+    let json = req.content_type() == "application/json";
+    let (converted, amount) = if json {
+        if params.precision.is_some_and(|p| p > MAX_PRECISION) {
+            return HttpResponse::BadRequest().body(format!("precision must not exceed {}\n", MAX_PRECISION));
+        }
+        let Ok(request) = serde_json::from_str::<MilkRequest>(&body) else {
+            return HttpResponse::BadRequest().finish();
+        };
+        let converted = match convert(&request, &params) {
+            Ok(converted) => converted,
+            Err(resp) => return resp,
+        };
+        let amount = request.liters().map_or(Ratio::integer(0), |l| l.abs());
+        (Some(converted), amount)
+    } else {
+        (None, Ratio::integer(1))
     };
 
-    if let Ok(VolumeUnit { liters, gallons, litres, pints }) = content {
-        let mut v = VolumeUnit { liters: None, gallons: None, litres: None, pints: None };
-        if (liters.is_some() && gallons.is_none() && litres.is_none() && pints.is_none()) ||
-            (liters.is_none() && gallons.is_some() && litres.is_none() && pints.is_none()) {
-            if let Some(liters) = liters {
-                // liters to gallons
-                let gallons = liters / 3.78541;
-                v.gallons = Some(gallons);
-            }
-            if let Some(gallons) = gallons {
-                // gallons to liters
-                let liters = gallons * 3.78541;
-                v.liters = Some(liters);
-            }
-            return HttpResponse::Ok().json(v);
-        } else if (litres.is_some() && pints.is_none() && liters.is_none() && gallons.is_none())
-            || (litres.is_none() && pints.is_some() && liters.is_none() && gallons.is_none()) {
-            if let Some(litres) = litres {
-                // litres to pints
-                let pints = litres * 1.75975;
-                v.pints = Some(pints);
-            }
-            if let Some(pints) = pints {
-                // pints to litres
-                let litres = pints / 1.75975;
-                v.litres = Some(litres);
-            }
-            return HttpResponse::Ok().json(v);
+    let amount = inventory::liters(&amount);
+    let mut tank = None;
+    if !amount.is_zero() {
+        match inventory::withdraw(&pool, &client_key(&req), &amount).await {
+            Ok(Outcome::Done(t)) => tank = Some(t),
+            Ok(Outcome::Rejected(t)) => return insufficient(json, &amount, &t),
+            Err(e) => return inventory::server_error(e),
+        }
+    }
+
+    let mut resp = match converted {
+        Some((key, value)) => number_response(&key, &value),
+        None => HttpResponse::Ok().body("Milk withdrawn\n"),
+    };
+    if let Some(tank) = tank {
+        resp.headers_mut()
+            .insert(HeaderName::from_static("x-milk-stock"), HeaderValue::from_str(&tank.stock.to_string()).unwrap());
+    }
+    resp
+}
+
+/// 补充的体积，如 {"value": 10, "unit": "gallons"}，unit 默认为升
+#[derive(Deserialize, Debug)]
+struct Refill {
+    value: Ratio,
+    #[serde(default = "default_refill_unit")]
+    unit: String,
+}
+
+fn default_refill_unit() -> String {
+    "liters".to_string()
+}
+
+/// 补充奶罐：没有请求体时补满，超出容量返回 409；补充成功后重置限流
+async fn refill(
+    body: String,
+    req: HttpRequest,
+    limiter: web::Data<Arc<dyn RateLimiter>>,
+    pool: web::Data<sqlx::PgPool>,
+) -> impl Responder {
+    let amount = if body.trim().is_empty() {
+        None
+    } else {
+        let Ok(Refill { value, unit }) = serde_json::from_str::<Refill>(&body) else {
+            return HttpResponse::BadRequest().finish();
+        };
+        match units::convert(&value, &unit, "liters") {
+            Ok((liters, _)) if !liters.is_negative() => Some(inventory::liters(&liters)),
+            Ok(_) => return HttpResponse::BadRequest().body("Refill volume must not be negative\n"),
+            Err(e) => return HttpResponse::BadRequest().body(format!("{}\n", e)),
         }
+    };
+
+    match inventory::refill(&pool, &client_key(&req), amount.as_ref()).await {
+        Ok(Outcome::Done(tank)) => {
+            limiter.reset();
+            HttpResponse::Ok().json(tank)
+        }
+        Ok(Outcome::Rejected(tank)) => HttpResponse::Conflict().json(tank),
+        Err(e) => inventory::server_error(e),
     }
+}
 
-    HttpResponse::BadRequest().finish()
+#[derive(Serialize)]
+struct BucketState {
+    key: String,
+    tokens: u32,
+    capacity: u32,
 }
 
-async fn refill(bucket: web::Data<Arc<TokenBucket>>) -> impl Responder {
-    bucket.refill().await;
-    HttpResponse::Ok().finish()
+async fn inspect(key: String, limiter: &dyn RateLimiter) -> HttpResponse {
+    HttpResponse::Ok().json(BucketState {
+        tokens: limiter.remaining(&key),
+        capacity: limiter.limit(),
+        key,
+    })
 }
 
+/// 查询调用者自己的桶
+async fn own_bucket(req: HttpRequest, limiter: web::Data<Arc<dyn RateLimiter>>) -> impl Responder {
+    inspect(client_key(&req), limiter.as_ref().as_ref()).await
+}
 
 pub(crate) fn scope() -> actix_web::Scope {
     web::scope("9")
-        .route("/milk", web::post().to(milk))
+        .service(
+            web::resource("/milk")
+                .wrap(RateLimit::shared().message("No milk available\n").cost(milk_cost))
+                .route(web::post().to(milk)),
+        )
         .route("/refill", web::post().to(refill))
-}
\ No newline at end of file
+        .route("/tank", web::get().to(inventory::tank))
+        .route("/ledger", web::get().to(inventory::ledger))
+        .route("/bucket", web::get().to(own_bucket))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert_json(body: &str) -> Result<(String, String), u16> {
+        let params = MilkParams {
+            precision: None,
+            rounding: Rounding::default(),
+        };
+        let request: MilkRequest = serde_json::from_str(body).map_err(|_| 400u16)?;
+        convert(&request, &params).map_err(|resp| resp.status().as_u16())
+    }
+
+    fn pair(key: &str, value: &str) -> Result<(String, String), u16> {
+        Ok((key.to_string(), value.to_string()))
+    }
+
+    #[test]
+    fn legacy_fields_keep_their_response_keys() {
+        assert_eq!(convert_json(r#"{"gallons": 1}"#), pair("liters", "3.785411784"));
+        assert_eq!(convert_json(r#"{"liters": 3.785411784}"#), pair("gallons", "1"));
+        assert_eq!(convert_json(r#"{"pints": 1}"#), pair("litres", "0.56826125"));
+        assert_eq!(convert_json(r#"{"litres": 0.56826125}"#), pair("pints", "1"));
+        assert_eq!(
+            convert_json(r#"{"liters": 1}"#),
+            pair("gallons", "0.26417205235814841538")
+        );
+    }
+
+    #[test]
+    fn legacy_requests_need_exactly_one_field() {
+        assert_eq!(convert_json(r#"{"liters": 1, "gallons": 1}"#), Err(400));
+        assert_eq!(convert_json(r#"{}"#), Err(400));
+    }
+
+    #[test]
+    fn conversions_use_canonical_unit_names() {
+        assert_eq!(convert_json(r#"{"value": 1000, "from": "g", "to": " KG"}"#), pair("kg", "1"));
+        assert_eq!(convert_json(r#"{"value": 100, "from": "celsius", "to": "F"}"#), pair("fahrenheit", "212"));
+        assert_eq!(convert_json(r#"{"value": 1, "from": "kg", "to": "liters"}"#), Err(400));
+        assert_eq!(convert_json(r#"{"value": -300, "from": "c", "to": "k"}"#), Err(400));
+    }
+
+    #[test]
+    fn json_numbers_are_read_exactly() {
+        assert_eq!(
+            convert_json(r#"{"gallons": 12345678901234567890123}"#),
+            pair("liters", "46733478394213505439419.621409432")
+        );
+        assert_eq!(convert_json(r#"{"value": 0.1, "from": "l", "to": "ml"}"#), pair("ml", "100"));
+        assert_eq!(convert_json(r#"{"value": "1/3", "from": "l", "to": "ml"}"#).unwrap().0, "ml");
+        assert_eq!(convert_json(r#"{"liters": "8/4/2"}"#), Err(400));
+    }
+
+    #[test]
+    fn only_volumes_cost_liters() {
+        let liters = |body: &str| serde_json::from_str::<MilkRequest>(body).unwrap().liters();
+        assert_eq!(liters(r#"{"pints": 2}"#), Some(Ratio::parse("1.1365225").unwrap()));
+        assert_eq!(liters(r#"{"value": 2, "from": "gal", "to": "l"}"#), Some(Ratio::parse("7.570823568").unwrap()));
+        assert_eq!(liters(r#"{"value": 2, "from": "kg", "to": "g"}"#), None);
+    }
+
+    #[sqlx::test]
+    async fn only_successful_refills_reset_the_limiter(pool: sqlx::PgPool) {
+        use std::time::Duration;
+        use actix_web::test::TestRequest;
+        use super::super::rate_limit::{Algorithm, SystemClock};
+
+        let limiter = Algorithm::TokenBucket.build(5, Duration::from_secs(5), Arc::new(SystemClock));
+        limiter.consume_n("k", 5);
+        let limiter = web::Data::new(limiter);
+        let pool = web::Data::new(pool);
+        let req = TestRequest::default().to_http_request();
+        let call = |body: &str| refill(body.to_string(), req.clone(), limiter.clone(), pool.clone());
+
+        let resp = call(r#"{"value": 1}"#).await.respond_to(&req);
+        assert_eq!(resp.status(), 409);
+        assert_eq!(limiter.remaining("k"), 0);
+
+        let resp = call("").await.respond_to(&req);
+        assert_eq!(resp.status(), 200);
+        assert_eq!(limiter.remaining("k"), 5);
+    }
+}
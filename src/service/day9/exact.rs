@@ -0,0 +1,321 @@
+use std::cmp::Ordering;
+use std::ops::{Add, Div, Mul, Sub};
+use num_bigint::{BigInt, Sign};
+use num_integer::Integer;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use serde::de::{self, Deserialize, Deserializer};
+use shuttle_runtime::__internals::serde_json::{self, value::RawValue};
+
+/// 精确的有理数，分母总是正数且已约分
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Ratio {
+    num: BigInt,
+    den: BigInt,
+}
+
+impl Ratio {
+    fn new(num: BigInt, den: BigInt) -> Self {
+        assert!(!den.is_zero(), "denominator must not be zero");
+        let gcd = num.gcd(&den);
+        let (mut num, mut den) = (num / &gcd, den / gcd);
+        if den.is_negative() {
+            num = -num;
+            den = -den;
+        }
+        Self { num, den }
+    }
+
+    pub(super) fn integer(value: i64) -> Self {
+        Self::new(value.into(), BigInt::one())
+    }
+
+    /// 解析 "-12.5"、"1.5e3" 这样的十进制数，或者 "5/9" 这样的分数。
+    /// 分数只能有一个 `/`，两边都是十进制数
+    pub(super) fn parse(s: &str) -> Option<Self> {
+        match s.trim().split_once('/') {
+            Some((num, den)) => {
+                let (num, den) = (Self::decimal(num.trim())?, Self::decimal(den.trim())?);
+                (!den.is_zero()).then(|| num / den)
+            }
+            None => Self::decimal(s.trim()),
+        }
+    }
+
+    fn decimal(s: &str) -> Option<Self> {
+        let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+            Some((m, e)) => (m, e.parse::<i32>().ok()?),
+            None => (s, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits = int.trim_start_matches(['+', '-']);
+        if digits.is_empty() && frac.is_empty()
+            || !digits.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+            || int.len() - digits.len() > 1
+        {
+            return None;
+        }
+
+        let mut num: BigInt = format!("{}{}", digits, frac).parse().ok()?;
+        if int.starts_with('-') {
+            num = -num;
+        }
+        // 指数太大时结果的位数过多，当作无效输入
+        let scale = exponent.checked_sub(frac.len() as i32).filter(|e| e.abs() <= 1000)?;
+        let pow = BigInt::from(10).pow(scale.unsigned_abs());
+        Some(if scale >= 0 {
+            Self::new(num * pow, BigInt::one())
+        } else {
+            Self::new(num, pow)
+        })
+    }
+
+    pub(super) fn is_zero(&self) -> bool {
+        self.num.is_zero()
+    }
+
+    pub(super) fn is_negative(&self) -> bool {
+        self.num.is_negative()
+    }
+
+    pub(super) fn abs(&self) -> Self {
+        Self {
+            num: self.num.abs(),
+            den: self.den.clone(),
+        }
+    }
+
+    /// 向上取整，超出 u32 时为 None
+    pub(super) fn ceil_u32(&self) -> Option<u32> {
+        let (q, r) = self.num.div_mod_floor(&self.den);
+        let q = if r.is_zero() { q } else { q + 1 };
+        q.to_u32()
+    }
+
+    /// 分母只含因子 2 和 5 时可以写成有限小数，返回需要的小数位数
+    pub(super) fn terminating_places(&self) -> Option<u32> {
+        let mut den = self.den.clone();
+        let mut places = (0, 0);
+        let (two, five) = (BigInt::from(2), BigInt::from(5));
+        while den.is_even() {
+            den /= &two;
+            places.0 += 1;
+        }
+        while (&den % &five).is_zero() {
+            den /= &five;
+            places.1 += 1;
+        }
+        den.is_one().then_some(places.0.max(places.1))
+    }
+
+    /// 按 mode 舍入到 places 位小数
+    pub(super) fn round(&self, places: u32, mode: Rounding) -> Self {
+        let scale = BigInt::from(10).pow(places);
+        let scaled = &self.num * &scale;
+        let (q, r) = scaled.div_mod_floor(&self.den);
+        if r.is_zero() {
+            return Self::new(q, scale);
+        }
+        // q 是向下取整的结果，r / den 是被舍掉的部分，在 (0, 1) 之间
+        let half = (&r * BigInt::from(2)).cmp(&self.den);
+        let negative = self.num.is_negative();
+        let up = match mode {
+            Rounding::Floor => false,
+            Rounding::Ceiling => true,
+            Rounding::Down => negative,
+            Rounding::Up => !negative,
+            Rounding::HalfUp => half == Ordering::Greater || half == Ordering::Equal && !negative,
+            Rounding::HalfDown => half == Ordering::Greater || half == Ordering::Equal && negative,
+            Rounding::HalfEven => half == Ordering::Greater || half == Ordering::Equal && q.is_odd(),
+        };
+        Self::new(if up { q + 1 } else { q }, scale)
+    }
+
+    /// 输出为十进制字符串。能写成不超过 max_places 位的有限小数时原样输出，
+    /// 否则按 mode 舍入到 max_places 位；末尾的 0 会去掉
+    pub(super) fn to_decimal(&self, max_places: u32, mode: Rounding) -> String {
+        let value = match self.terminating_places() {
+            Some(places) if places <= max_places => self.clone(),
+            _ => self.round(max_places, mode),
+        };
+        let places = value.terminating_places().expect("rounded value is a terminating decimal");
+        let scaled = &value.num * BigInt::from(10).pow(places) / &value.den;
+
+        let digits = scaled.magnitude().to_string();
+        let sign = if scaled.sign() == Sign::Minus { "-" } else { "" };
+        if places == 0 {
+            return format!("{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = places as usize + 1);
+        let (int, frac) = digits.split_at(digits.len() - places as usize);
+        format!("{}{}.{}", sign, int, frac)
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Ratio) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Ratio) -> Ordering {
+        // 分母都是正数，交叉相乘不改变大小关系
+        (&self.num * &other.den).cmp(&(&other.num * &self.den))
+    }
+}
+
+impl Add for &Ratio {
+    type Output = Ratio;
+
+    fn add(self, other: &Ratio) -> Ratio {
+        Ratio::new(&self.num * &other.den + &other.num * &self.den, &self.den * &other.den)
+    }
+}
+
+impl Sub for &Ratio {
+    type Output = Ratio;
+
+    fn sub(self, other: &Ratio) -> Ratio {
+        Ratio::new(&self.num * &other.den - &other.num * &self.den, &self.den * &other.den)
+    }
+}
+
+impl Mul for &Ratio {
+    type Output = Ratio;
+
+    fn mul(self, other: &Ratio) -> Ratio {
+        Ratio::new(&self.num * &other.num, &self.den * &other.den)
+    }
+}
+
+/// 除数不能为 0
+impl Div for &Ratio {
+    type Output = Ratio;
+
+    fn div(self, other: &Ratio) -> Ratio {
+        Ratio::new(&self.num * &other.den, &self.den * &other.num)
+    }
+}
+
+impl Div for Ratio {
+    type Output = Ratio;
+
+    fn div(self, other: Ratio) -> Ratio {
+        &self / &other
+    }
+}
+
+/// 舍入方式，与 Java 的 RoundingMode 含义相同
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Rounding {
+    /// 远离 0
+    Up,
+    /// 趋向 0，即截断
+    Down,
+    Ceiling,
+    Floor,
+    HalfUp,
+    HalfDown,
+    /// 银行家舍入
+    #[default]
+    HalfEven,
+}
+
+/// JSON 数字按原文精确解析，不经过 f64；字符串还可以写成分数，如 "1/3"。
+/// 需要 serde_json 的 raw_value 特性（sqlx 已经打开），只能用于 serde_json
+impl<'de> Deserialize<'de> for Ratio {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = <Box<RawValue>>::deserialize(deserializer)?;
+        let text = raw.get();
+        let value = if text.starts_with('"') {
+            serde_json::from_str::<String>(text).ok().and_then(|s| Ratio::parse(&s))
+        } else if text.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+            Ratio::decimal(text)
+        } else {
+            None
+        };
+        value.ok_or_else(|| de::Error::invalid_value(de::Unexpected::Other(text), &"a number or a decimal string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(s: &str) -> Ratio {
+        Ratio::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_decimals_and_fractions() {
+        assert_eq!(value("-12.5"), Ratio::new((-25).into(), 2.into()));
+        assert_eq!(value("1.5e3"), Ratio::integer(1500));
+        assert_eq!(value("+.5"), value("1/2"));
+        assert_eq!(value(" 5 / 9 "), Ratio::new(5.into(), 9.into()));
+        assert_eq!(value("1.5/0.5"), Ratio::integer(3));
+        for bad in ["", "-", "1.2.3", "--1", "1e", "1/0", "8/4/2", "1/", "/2", "abc", "1e2000"] {
+            assert_eq!(Ratio::parse(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn long_fraction_chains_are_rejected_without_recursion() {
+        let chain = "1/".repeat(120_000) + "1";
+        assert_eq!(Ratio::parse(&chain), None);
+    }
+
+    #[test]
+    fn rounds_in_every_mode() {
+        use Rounding::*;
+        let cases = [
+            // (value, Up, Down, Ceiling, Floor, HalfUp, HalfDown, HalfEven)
+            ("2.5", ["3", "2", "3", "2", "3", "2", "2"]),
+            ("3.5", ["4", "3", "4", "3", "4", "3", "4"]),
+            ("2.4", ["3", "2", "3", "2", "2", "2", "2"]),
+            ("2.6", ["3", "2", "3", "2", "3", "3", "3"]),
+            ("-2.5", ["-3", "-2", "-2", "-3", "-3", "-2", "-2"]),
+            ("-2.6", ["-3", "-2", "-2", "-3", "-3", "-3", "-3"]),
+            ("-2.4", ["-3", "-2", "-2", "-3", "-2", "-2", "-2"]),
+            ("2", ["2", "2", "2", "2", "2", "2", "2"]),
+        ];
+        for (input, expected) in cases {
+            for (mode, want) in [Up, Down, Ceiling, Floor, HalfUp, HalfDown, HalfEven].into_iter().zip(expected) {
+                assert_eq!(value(input).round(0, mode), value(want), "{} {:?}", input, mode);
+            }
+        }
+        assert_eq!(value("1/3").round(2, HalfEven), value("0.33"));
+        assert_eq!(value("-2/3").round(2, HalfEven), value("-0.67"));
+    }
+
+    #[test]
+    fn formats_decimals() {
+        assert_eq!(value("1.500").to_decimal(6, Rounding::HalfEven), "1.5");
+        assert_eq!(value("-0.05").to_decimal(6, Rounding::HalfEven), "-0.05");
+        assert_eq!(value("1e3").to_decimal(6, Rounding::HalfEven), "1000");
+        assert_eq!(value("1/3").to_decimal(4, Rounding::HalfEven), "0.3333");
+        assert_eq!(value("2/3").to_decimal(4, Rounding::Down), "0.6666");
+        assert_eq!(value("-1/3").to_decimal(2, Rounding::Floor), "-0.34");
+        assert_eq!(value("0.0001").to_decimal(2, Rounding::HalfEven), "0");
+    }
+
+    #[test]
+    fn deserializes_json_exactly() {
+        let json = |s: &str| serde_json::from_str::<Ratio>(s).ok();
+        assert_eq!(json("12345678901234567890123"), Some(value("12345678901234567890123")));
+        assert_eq!(json("0.1"), Some(value("1/10")));
+        assert_eq!(json("-1.5E-2"), Some(value("-0.015")));
+        assert_eq!(json(r#""1/3""#), Some(value("1/3")));
+        assert_eq!(json(r#""8/4/2""#), None);
+        assert_eq!(json("true"), None);
+        assert_eq!(json("null"), None);
+    }
+
+    #[test]
+    fn gallons_round_trip_through_liters() {
+        let per_gallon = value("3.785411784");
+        let gallons = value("12345678901234567890123.456");
+        let liters = &gallons * &per_gallon;
+        assert_eq!(&liters / &per_gallon, gallons);
+    }
+}
@@ -0,0 +1,246 @@
+use actix_web::web::Query;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use super::exact::{Ratio, Rounding};
+
+/// 目前只有一个奶罐，由 migration 创建
+const TANK: &str = "default";
+/// 与数据库中 NUMERIC(15, 6) 的小数位数一致
+const SCALE: u32 = 6;
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+/// 升；NUMERIC(15, 6) 最多 15 位有效数字，转成 f64 输出不会丢失精度
+#[derive(Serialize, Debug)]
+pub(super) struct Tank {
+    pub(super) capacity: f64,
+    pub(super) stock: f64,
+}
+
+struct Levels {
+    capacity: Ratio,
+    stock: Ratio,
+}
+
+impl Levels {
+    fn view(&self) -> Tank {
+        Tank {
+            capacity: to_f64(&self.capacity),
+            stock: to_f64(&self.stock),
+        }
+    }
+}
+
+pub(super) fn to_f64(value: &Ratio) -> f64 {
+    value
+        .to_decimal(SCALE, Rounding::HalfEven)
+        .parse()
+        .expect("decimal string parses as f64")
+}
+
+fn numeric(value: &str) -> Ratio {
+    Ratio::parse(value).expect("NUMERIC is a valid decimal")
+}
+
+/// 按数据库的精度舍入，写入 NUMERIC 时使用
+pub(super) fn liters(value: &Ratio) -> Ratio {
+    value.round(SCALE, Rounding::HalfEven)
+}
+
+/// 流水中记录的客户端标识，不可逆，只用来区分不同的客户端
+fn client_id(client: &str) -> String {
+    hex::encode(&Sha256::digest(client.as_bytes())[..8])
+}
+
+pub(super) enum Outcome {
+    Done(Tank),
+    /// 库存不足或者会溢出，库存没有变化
+    Rejected(Tank),
+}
+
+/// 锁住奶罐，按 update 计算新的库存并记入流水；update 返回 None 时不做修改
+async fn adjust(
+    pool: &PgPool,
+    client: &str,
+    update: impl FnOnce(&Levels) -> Option<Ratio>,
+) -> Result<Outcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query!(
+        r#"SELECT capacity::TEXT AS "capacity!", stock::TEXT AS "stock!"
+        FROM milk_tanks WHERE name = $1 FOR UPDATE"#,
+        TANK
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let mut levels = Levels {
+        capacity: numeric(&row.capacity),
+        stock: numeric(&row.stock),
+    };
+
+    let Some(stock) = update(&levels).filter(|s| !s.is_negative() && *s <= levels.capacity) else {
+        return Ok(Outcome::Rejected(levels.view()));
+    };
+    let change = &stock - &levels.stock;
+    let stock_text = stock.to_decimal(SCALE, Rounding::HalfEven);
+    let change_text = change.to_decimal(SCALE, Rounding::HalfEven);
+
+    sqlx::query!(
+        "UPDATE milk_tanks SET stock = $2::TEXT::NUMERIC, updated_at = CURRENT_TIMESTAMP WHERE name = $1",
+        TANK,
+        stock_text
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO milk_ledger (tank, client, change, stock)
+        VALUES ($1, $2, $3::TEXT::NUMERIC, $4::TEXT::NUMERIC)",
+        TANK,
+        client_id(client),
+        change_text,
+        stock_text
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    levels.stock = stock;
+    Ok(Outcome::Done(levels.view()))
+}
+
+/// 取出 amount 升，库存不足时拒绝
+pub(super) async fn withdraw(pool: &PgPool, client: &str, amount: &Ratio) -> Result<Outcome, sqlx::Error> {
+    adjust(pool, client, |levels| Some(&levels.stock - amount)).await
+}
+
+/// 补充 amount 升，超出容量时拒绝；没有 amount 时补满
+pub(super) async fn refill(pool: &PgPool, client: &str, amount: Option<&Ratio>) -> Result<Outcome, sqlx::Error> {
+    adjust(pool, client, |levels| match amount {
+        Some(amount) => Some(&levels.stock + amount),
+        None => Some(levels.capacity.clone()),
+    })
+    .await
+}
+
+pub(super) fn server_error(e: sqlx::Error) -> HttpResponse {
+    eprintln!("Database error: {}", e);
+    HttpResponse::InternalServerError().finish()
+}
+
+pub(super) async fn tank(pool: web::Data<PgPool>) -> HttpResponse {
+    match sqlx::query_as!(
+        Tank,
+        r#"SELECT capacity::FLOAT8 AS "capacity!", stock::FLOAT8 AS "stock!"
+        FROM milk_tanks WHERE name = $1"#,
+        TANK
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    {
+        Ok(tank) => HttpResponse::Ok().json(tank),
+        Err(e) => server_error(e),
+    }
+}
+
+#[derive(Serialize, FromRow)]
+struct Entry {
+    id: i64,
+    change: f64,
+    stock: f64,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct LedgerParams {
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: i64,
+}
+
+/// 奶罐的出入流水，最新的在前
+pub(super) async fn ledger(params: Query<LedgerParams>, pool: web::Data<PgPool>) -> HttpResponse {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = params.offset.max(0);
+
+    #[derive(Serialize)]
+    struct Ledger {
+        entries: Vec<Entry>,
+    }
+    match sqlx::query_as!(
+        Entry,
+        r#"SELECT id, change::FLOAT8 AS "change!", stock::FLOAT8 AS "stock!", created_at
+        FROM milk_ledger WHERE tank = $1
+        ORDER BY id DESC
+        LIMIT $2 OFFSET $3"#,
+        TANK,
+        limit,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    {
+        Ok(entries) => HttpResponse::Ok().json(Ledger { entries }),
+        Err(e) => server_error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::to_bytes;
+    use shuttle_runtime::__internals::serde_json;
+    use super::*;
+
+    fn value(s: &str) -> Ratio {
+        Ratio::parse(s).unwrap()
+    }
+
+    fn stock(outcome: Outcome) -> Result<f64, f64> {
+        match outcome {
+            Outcome::Done(tank) => Ok(tank.stock),
+            Outcome::Rejected(tank) => Err(tank.stock),
+        }
+    }
+
+    #[sqlx::test]
+    async fn rejects_withdrawals_beyond_stock(pool: PgPool) {
+        assert_eq!(stock(withdraw(&pool, "ip:1.2.3.4", &value("1000.000001")).await.unwrap()), Err(1000.0));
+        assert_eq!(stock(withdraw(&pool, "ip:1.2.3.4", &value("999.5")).await.unwrap()), Ok(0.5));
+        assert_eq!(stock(withdraw(&pool, "ip:1.2.3.4", &value("1")).await.unwrap()), Err(0.5));
+        let rows = sqlx::query_scalar!("SELECT COUNT(*) FROM milk_ledger").fetch_one(&pool).await.unwrap();
+        assert_eq!(rows, Some(1));
+    }
+
+    #[sqlx::test]
+    async fn rejects_refills_beyond_capacity(pool: PgPool) {
+        withdraw(&pool, "ip:1.2.3.4", &value("10")).await.unwrap();
+        assert_eq!(stock(refill(&pool, "ip:1.2.3.4", Some(&value("10.5"))).await.unwrap()), Err(990.0));
+        assert_eq!(stock(refill(&pool, "ip:1.2.3.4", Some(&value("4"))).await.unwrap()), Ok(994.0));
+        assert_eq!(stock(refill(&pool, "ip:1.2.3.4", None).await.unwrap()), Ok(1000.0));
+    }
+
+    #[sqlx::test]
+    async fn ledger_lists_changes_without_client_keys(pool: PgPool) {
+        withdraw(&pool, "key:secret", &value("2.5")).await.unwrap();
+        refill(&pool, "sub:alice", None).await.unwrap();
+
+        let clients = sqlx::query_scalar!("SELECT client FROM milk_ledger ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(clients, [client_id("key:secret"), client_id("sub:alice")]);
+        assert_eq!(clients[0].len(), 16);
+
+        let params = Query(LedgerParams { limit: None, offset: 0 });
+        let resp = ledger(params, web::Data::new(pool)).await;
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let entries = body["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((&entries[0]["change"], &entries[0]["stock"]), (&2.5.into(), &1000.0.into()));
+        assert_eq!((&entries[1]["change"], &entries[1]["stock"]), (&(-2.5).into(), &997.5.into()));
+        assert!(entries.iter().all(|e| e.get("client").is_none()));
+    }
+}
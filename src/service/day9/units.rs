@@ -0,0 +1,227 @@
+use std::fmt;
+use super::exact::Ratio;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Dimension {
+    Volume,
+    Mass,
+    Temperature,
+    Length,
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Dimension::Volume => "volume",
+            Dimension::Mass => "mass",
+            Dimension::Temperature => "temperature",
+            Dimension::Length => "length",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Unit {
+    /// 规范名称，响应中用作键
+    pub(super) name: &'static str,
+    pub(super) dimension: Dimension,
+    /// 换算到基本单位（l、kg、K、m）：base = value * factor + offset，都是精确值
+    factor: Ratio,
+    /// 只有温度用到
+    offset: Ratio,
+}
+
+fn exact(value: &str) -> Ratio {
+    Ratio::parse(value).expect("unit constants are valid decimals")
+}
+
+fn linear(name: &'static str, dimension: Dimension, factor: &str) -> Unit {
+    Unit {
+        name,
+        dimension,
+        factor: exact(factor),
+        offset: Ratio::integer(0),
+    }
+}
+
+fn temperature(name: &'static str, factor: &str, offset: &str) -> Unit {
+    Unit {
+        name,
+        dimension: Dimension::Temperature,
+        factor: exact(factor),
+        offset: exact(offset),
+    }
+}
+
+/// 按名称查找单位，忽略大小写，单复数和常见缩写都可以。
+/// 不带前缀的 gallon 是美制（与原来的 gallons 字段一致），pint 是英制（与 pints 字段一致）
+pub(super) fn lookup(name: &str) -> Option<Unit> {
+    use Dimension::*;
+    let unit = match name.trim().to_ascii_lowercase().as_str() {
+        // 体积，基本单位 l
+        "ml" | "milliliter" | "milliliters" | "millilitre" | "millilitres" => linear("ml", Volume, "1e-3"),
+        "cl" | "centiliter" | "centiliters" | "centilitre" | "centilitres" => linear("cl", Volume, "1e-2"),
+        "dl" | "deciliter" | "deciliters" | "decilitre" | "decilitres" => linear("dl", Volume, "1e-1"),
+        "l" | "liter" | "liters" | "litre" | "litres" => linear("l", Volume, "1.0"),
+        "m3" | "cubic_meter" | "cubic_meters" | "cubic_metre" | "cubic_metres" => linear("m3", Volume, "1e3"),
+        "gal" | "gallon" | "gallons" | "us_gallon" | "us_gallons" => linear("gal", Volume, "3.785411784"),
+        "us_quart" | "us_quarts" | "qt" | "quart" | "quarts" => linear("us_quart", Volume, "0.946352946"),
+        "us_pint" | "us_pints" => linear("us_pint", Volume, "0.473176473"),
+        "us_cup" | "us_cups" | "cup" | "cups" => linear("us_cup", Volume, "0.2365882365"),
+        "us_fluid_ounce" | "us_fluid_ounces" | "fl_oz" | "fluid_ounce" | "fluid_ounces" => {
+            linear("us_fluid_ounce", Volume, "0.0295735295625")
+        }
+        "tbsp" | "tablespoon" | "tablespoons" => linear("tbsp", Volume, "0.01478676478125"),
+        "tsp" | "teaspoon" | "teaspoons" => linear("tsp", Volume, "0.00492892159375"),
+        "imperial_gallon" | "imperial_gallons" => linear("imperial_gallon", Volume, "4.54609"),
+        "imperial_quart" | "imperial_quarts" => linear("imperial_quart", Volume, "1.1365225"),
+        "pt" | "pint" | "pints" | "imperial_pint" | "imperial_pints" => linear("pt", Volume, "0.56826125"),
+        "imperial_fluid_ounce" | "imperial_fluid_ounces" => linear("imperial_fluid_ounce", Volume, "0.0284130625"),
+        // 质量，基本单位 kg
+        "mg" | "milligram" | "milligrams" => linear("mg", Mass, "1e-6"),
+        "g" | "gram" | "grams" => linear("g", Mass, "1e-3"),
+        "kg" | "kilogram" | "kilograms" => linear("kg", Mass, "1.0"),
+        "t" | "tonne" | "tonnes" | "metric_ton" | "metric_tons" => linear("t", Mass, "1e3"),
+        "oz" | "ounce" | "ounces" => linear("oz", Mass, "0.028349523125"),
+        "lb" | "lbs" | "pound" | "pounds" => linear("lb", Mass, "0.45359237"),
+        "st" | "stone" | "stones" => linear("st", Mass, "6.35029318"),
+        "short_ton" | "short_tons" | "us_ton" | "us_tons" => linear("short_ton", Mass, "907.18474"),
+        "long_ton" | "long_tons" | "imperial_ton" | "imperial_tons" => linear("long_ton", Mass, "1016.0469088"),
+        // 温度，基本单位 K
+        "k" | "kelvin" => temperature("kelvin", "1", "0"),
+        "c" | "celsius" => temperature("celsius", "1", "273.15"),
+        // 偏移量为 273.15 - 32 * 5/9
+        "f" | "fahrenheit" => temperature("fahrenheit", "5/9", "45967/180"),
+        "r" | "rankine" => temperature("rankine", "5/9", "0"),
+        // 长度，基本单位 m
+        "mm" | "millimeter" | "millimeters" | "millimetre" | "millimetres" => linear("mm", Length, "1e-3"),
+        "cm" | "centimeter" | "centimeters" | "centimetre" | "centimetres" => linear("cm", Length, "1e-2"),
+        "m" | "meter" | "meters" | "metre" | "metres" => linear("m", Length, "1.0"),
+        "km" | "kilometer" | "kilometers" | "kilometre" | "kilometres" => linear("km", Length, "1e3"),
+        "in" | "inch" | "inches" => linear("in", Length, "0.0254"),
+        "ft" | "foot" | "feet" => linear("ft", Length, "0.3048"),
+        "yd" | "yard" | "yards" => linear("yd", Length, "0.9144"),
+        "mi" | "mile" | "miles" => linear("mi", Length, "1609.344"),
+        "nmi" | "nautical_mile" | "nautical_miles" => linear("nmi", Length, "1852.0"),
+        _ => return None,
+    };
+    Some(unit)
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum ConversionError {
+    UnknownUnit(String),
+    Incompatible { from: Dimension, to: Dimension },
+    /// 低于绝对零度
+    OutOfRange,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::UnknownUnit(name) => write!(f, "Unknown unit '{}'", name),
+            ConversionError::Incompatible { from, to } => write!(f, "Cannot convert {} to {}", from, to),
+            ConversionError::OutOfRange => f.write_str("Value out of range"),
+        }
+    }
+}
+
+fn unit(name: &str) -> Result<Unit, ConversionError> {
+    lookup(name).ok_or_else(|| ConversionError::UnknownUnit(name.to_string()))
+}
+
+impl Unit {
+    pub(super) fn to_base(&self, value: &Ratio) -> Ratio {
+        &(value * &self.factor) + &self.offset
+    }
+
+    fn to_unit(&self, value: &Ratio) -> Ratio {
+        &(value - &self.offset) / &self.factor
+    }
+}
+
+/// 把 from 单位的 value 换算到 to 单位，量纲必须相同，同时返回 to 的规范名称。
+/// 全程使用有理数，换算过去再换算回来得到的就是原值
+pub(super) fn convert(value: &Ratio, from: &str, to: &str) -> Result<(Ratio, &'static str), ConversionError> {
+    let (from, to) = (unit(from)?, unit(to)?);
+    if from.dimension != to.dimension {
+        return Err(ConversionError::Incompatible {
+            from: from.dimension,
+            to: to.dimension,
+        });
+    }
+    let base = from.to_base(value);
+    if from.dimension == Dimension::Temperature && base.is_negative() {
+        return Err(ConversionError::OutOfRange);
+    }
+    Ok((to.to_unit(&base), to.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(s: &str) -> Ratio {
+        Ratio::parse(s).unwrap()
+    }
+
+    fn convert_str(v: &str, from: &str, to: &str) -> Result<(Ratio, &'static str), ConversionError> {
+        convert(&value(v), from, to)
+    }
+
+    #[test]
+    fn returns_canonical_names() {
+        assert_eq!(convert_str("1", "g", " KG"), Ok((value("0.001"), "kg")));
+        assert_eq!(convert_str("1", "kg", "Pounds").unwrap().1, "lb");
+        assert_eq!(convert_str("1", "l", "gallons").unwrap().1, "gal");
+        assert_eq!(convert_str("0", "c", "F").unwrap().1, "fahrenheit");
+    }
+
+    #[test]
+    fn rejects_unknown_and_incompatible_units() {
+        assert_eq!(convert_str("1", "kg", "parsec"), Err(ConversionError::UnknownUnit("parsec".to_string())));
+        assert_eq!(
+            convert_str("1", "kg", "l"),
+            Err(ConversionError::Incompatible {
+                from: Dimension::Mass,
+                to: Dimension::Volume
+            })
+        );
+        assert_eq!(
+            convert_str("1", "celsius", "m").unwrap_err().to_string(),
+            "Cannot convert temperature to length"
+        );
+    }
+
+    #[test]
+    fn applies_temperature_offsets() {
+        assert_eq!(convert_str("100", "c", "f").unwrap().0, value("212"));
+        assert_eq!(convert_str("-40", "f", "c").unwrap().0, value("-40"));
+        assert_eq!(convert_str("0", "c", "k").unwrap().0, value("273.15"));
+        assert_eq!(convert_str("32", "f", "k").unwrap().0, value("273.15"));
+        assert_eq!(convert_str("0", "f", "r").unwrap().0, value("459.67"));
+        // 差值不是温度，偏移量不能当成比例
+        assert_eq!(convert_str("10", "k", "c").unwrap().0, value("-263.15"));
+    }
+
+    #[test]
+    fn rejects_temperatures_below_absolute_zero() {
+        assert_eq!(convert_str("-273.15", "c", "k").unwrap().0, value("0"));
+        assert_eq!(convert_str("-459.67", "f", "c").unwrap().0, value("-273.15"));
+        assert_eq!(convert_str("-273.16", "c", "f"), Err(ConversionError::OutOfRange));
+        assert_eq!(convert_str("-1", "k", "k"), Err(ConversionError::OutOfRange));
+        assert_eq!(convert_str("-459.68", "f", "r"), Err(ConversionError::OutOfRange));
+        // 其他量纲允许负数
+        assert_eq!(convert_str("-1", "m", "cm").unwrap().0, value("-100"));
+    }
+
+    #[test]
+    fn round_trips_exactly() {
+        let start = value("1/3");
+        let (gallons, _) = convert(&start, "l", "gal").unwrap();
+        let (pints, _) = convert(&gallons, "gal", "pt").unwrap();
+        let (back, _) = convert(&pints, "pt", "l").unwrap();
+        assert_eq!(back, start);
+    }
+}
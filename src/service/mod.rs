@@ -9,6 +9,7 @@ pub mod day12;
 pub mod day16;
 pub mod day19;
 pub mod day23;
+pub mod rate_limit;
 
 
 pub fn generate_token(n :usize) -> String {
@@ -0,0 +1,746 @@
+use std::collections::{HashMap, HashSet};
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::{InternalError, PayloadError};
+use actix_web::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::web::Bytes;
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse};
+use futures::future::LocalBoxFuture;
+use futures::{stream, Stream};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+mod fixed_window;
+mod leaky_bucket;
+mod sliding_log;
+mod sliding_window;
+mod token_bucket;
+
+pub use fixed_window::FixedWindow;
+pub use leaky_bucket::LeakyBucket;
+pub use sliding_log::SlidingLog;
+pub use sliding_window::SlidingWindow;
+pub use token_bucket::TokenBuckets;
+
+/// 时间来源，测试时可以换成手动推进的实现
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// 手动推进的时钟，测试中按给定的请求序列重放
+#[cfg(test)]
+pub(crate) struct ManualClock(Mutex<Instant>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub(crate) fn new(start: Instant) -> Self {
+        Self(Mutex::new(start))
+    }
+
+    pub(crate) fn set(&self, now: Instant) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// 一次 consume 的结果
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    pub allowed: bool,
+    /// 本次请求之后还能立即通过的请求数，被拒绝时是当前可用的数量
+    pub remaining: u32,
+    /// 距离这次请求（被拒绝时）或下一个单位请求（通过时）能通过还要多久；
+    /// 消耗超过 limit 的请求永远不会通过，此时为 0
+    pub retry_after: Duration,
+    /// 请求被接受但需要排队等待的时间，只有 leaky bucket 会用到
+    pub delay: Duration,
+}
+
+impl Usage {
+    fn rejected(remaining: u32, retry_after: Duration) -> Self {
+        Self {
+            allowed: false,
+            remaining,
+            retry_after,
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+/// 各种限流算法的共同接口，状态按 key 分开
+pub trait RateLimiter: Send + Sync {
+    /// 一次消耗 n 个单位，不够时整体拒绝，不会部分扣除
+    fn consume_n(&self, key: &str, n: u32) -> Usage;
+    /// 某个 key 当前还能立即通过的请求数，没有记录的 key 返回 limit
+    fn remaining(&self, key: &str) -> u32;
+    fn limit(&self) -> u32;
+    /// 清空所有 key 的状态
+    fn reset(&self);
+}
+
+trait Slot {
+    fn last_seen(&self) -> Instant;
+}
+
+/// 按 key 保存限流状态，顺带清理超过 idle 没有请求的 key
+struct Keyed<T> {
+    idle: Duration,
+    state: Mutex<(HashMap<String, T>, Instant)>,
+}
+
+impl<T: Slot> Keyed<T> {
+    fn new(idle: Duration, now: Instant) -> Self {
+        Self {
+            idle,
+            state: Mutex::new((HashMap::new(), now)),
+        }
+    }
+
+    /// now 在拿到锁之后才读取，同一个 key 上看到的时间不会倒退
+    fn with<R>(&self, key: &str, clock: &dyn Clock, init: impl FnOnce(Instant) -> T, f: impl FnOnce(&mut T, Instant) -> R) -> R {
+        let mut state = self.state.lock().unwrap();
+        let now = clock.now();
+        let (slots, last_sweep) = &mut *state;
+        // 每个 idle 周期最多清理一次
+        if now.saturating_duration_since(*last_sweep) >= self.idle {
+            slots.retain(|_, slot| now.saturating_duration_since(slot.last_seen()) < self.idle);
+            *last_sweep = now;
+        }
+        f(slots.entry(key.to_string()).or_insert_with(|| init(now)), now)
+    }
+
+    fn peek<R>(&self, key: &str, clock: &dyn Clock, f: impl FnOnce(Option<&mut T>, Instant) -> R) -> R {
+        let mut state = self.state.lock().unwrap();
+        let now = clock.now();
+        f(state.0.get_mut(key), now)
+    }
+
+    fn clear(&self) {
+        self.state.lock().unwrap().0.clear();
+    }
+}
+
+/// 识别客户端用的配置，在 app_data 中注册；没有注册时不认任何 API key 和 JWT，也不信任代理
+#[derive(Debug, Default)]
+pub struct ClientIdentity {
+    api_keys: HashSet<String>,
+    /// 只有来自这些地址的请求才读取 X-Forwarded-For / Forwarded
+    trusted_proxies: HashSet<IpAddr>,
+    /// 验证 Bearer JWT 的 HS256 密钥；没有配置时忽略 Authorization
+    jwt_secret: Option<Vec<u8>>,
+}
+
+impl ClientIdentity {
+    pub fn new(api_keys: impl IntoIterator<Item = String>, trusted_proxies: impl IntoIterator<Item = IpAddr>) -> Self {
+        Self {
+            api_keys: api_keys.into_iter().collect(),
+            trusted_proxies: trusted_proxies.into_iter().collect(),
+            jwt_secret: None,
+        }
+    }
+
+    pub fn jwt_secret(mut self, secret: impl Into<Vec<u8>>) -> Self {
+        self.jwt_secret = Some(secret.into());
+        self
+    }
+
+    /// 从环境变量 RATE_LIMIT_API_KEYS 和 RATE_LIMIT_TRUSTED_PROXIES 读取，逗号分隔；
+    /// RATE_LIMIT_JWT_SECRET 为空或未设置时不按 JWT 区分客户端
+    pub fn from_env() -> Self {
+        let list = |name| {
+            std::env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        let proxies = list("RATE_LIMIT_TRUSTED_PROXIES")
+            .into_iter()
+            .map(|p| p.parse().unwrap_or_else(|_| panic!("Invalid trusted proxy address {:?}", p)));
+        let identity = Self::new(list("RATE_LIMIT_API_KEYS"), proxies);
+        match std::env::var("RATE_LIMIT_JWT_SECRET") {
+            Ok(secret) if !secret.is_empty() => identity.jwt_secret(secret),
+            _ => identity,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+/// 依次使用配置过的 X-Api-Key、Bearer JWT 的 sub、客户端 IP 作为限流的 key
+pub fn client_key(req: &HttpRequest) -> String {
+    let default = ClientIdentity::default();
+    let identity = req
+        .app_data::<web::Data<Arc<ClientIdentity>>>()
+        .map_or(&default, |i| i.get_ref().as_ref());
+    let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+    // 未知的 key 不单独计数，否则换一个 header 就能拿到新的额度
+    if let Some(key) = header("X-Api-Key").filter(|k| identity.api_keys.contains(*k)) {
+        return format!("key:{}", key);
+    }
+
+    // 签名密钥公开时任何人都能伪造 sub，所以必须单独配置
+    let token = header("Authorization").and_then(|v| v.strip_prefix("Bearer "));
+    if let (Some(token), Some(secret)) = (token, &identity.jwt_secret) {
+        let mut validation = Validation::new(jsonwebtoken::Algorithm::HS256);
+        validation.required_spec_claims = HashSet::from(["sub".to_string()]);
+        // 签名不对的 token 按 IP 限流
+        if let Ok(data) = decode::<Claims>(token, &DecodingKey::from_secret(secret), &validation) {
+            return format!("sub:{}", data.claims.sub);
+        }
+    }
+
+    let peer = req.peer_addr().map(|addr| addr.ip());
+    match peer {
+        // 转发头由客户端填写，只有经过可信代理时才采用
+        Some(ip) if identity.trusted_proxies.contains(&ip) => {
+            let info = req.connection_info();
+            format!("ip:{}", info.realip_remote_addr().unwrap_or("unknown"))
+        }
+        Some(ip) => format!("ip:{}", ip),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// 没有请求超过这段时间的 key 会被清理
+const IDLE: Duration = Duration::from_secs(300);
+
+/// 可以按路由选择的限流算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// 容量为 limit，每个 window 补满；允许突发
+    TokenBucket,
+    /// 请求排队，每个 window 放行 limit 个，超出的请求被延迟而不是立即通过
+    LeakyBucket,
+    /// 按 window 对齐的固定窗口，交界处可能连续通过 2 * limit 个
+    FixedWindow,
+    /// 精确的滑动窗口，每个 key 要保存 limit 个时间戳
+    SlidingLog,
+    /// 用上一个窗口的计数按比例估算滑动窗口
+    SlidingWindow,
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "token_bucket" => Algorithm::TokenBucket,
+            "leaky_bucket" => Algorithm::LeakyBucket,
+            "fixed_window" => Algorithm::FixedWindow,
+            "sliding_log" => Algorithm::SlidingLog,
+            "sliding_window" => Algorithm::SlidingWindow,
+            _ => return Err(format!("unknown rate limit algorithm {:?}", s)),
+        })
+    }
+}
+
+impl Algorithm {
+    pub fn build(self, limit: u32, window: Duration, clock: Arc<dyn Clock>) -> Arc<dyn RateLimiter> {
+        let rate = limit as f64 / window.as_secs_f64();
+        match self {
+            Algorithm::TokenBucket => Arc::new(TokenBuckets::with_clock(limit, rate, IDLE, clock)),
+            Algorithm::LeakyBucket => Arc::new(LeakyBucket::with_clock(limit, rate, IDLE, clock)),
+            Algorithm::FixedWindow => Arc::new(FixedWindow::with_clock(limit, window, IDLE, clock)),
+            Algorithm::SlidingLog => Arc::new(SlidingLog::with_clock(limit, window, IDLE, clock)),
+            Algorithm::SlidingWindow => Arc::new(SlidingWindow::with_clock(limit, window, IDLE, clock)),
+        }
+    }
+}
+
+/// 限流中间件，每个响应都带上 Retry-After、X-RateLimit-Limit、X-RateLimit-Remaining。
+///
+/// actix 会为每个 worker 调用一次 app 工厂，所以要在工厂外创建再 clone 进去，
+/// 否则每个 worker 各有一套状态
+#[derive(Clone)]
+pub struct RateLimit {
+    /// None 时使用 app_data 中的 `Arc<dyn RateLimiter>`
+    limiter: Option<Arc<dyn RateLimiter>>,
+    message: &'static str,
+    cost: Option<Cost>,
+}
+
+/// 根据请求和完整的请求体计算这次请求要消耗多少个单位
+pub type Cost = fn(&HttpRequest, &[u8]) -> u32;
+
+impl RateLimit {
+    /// 每个长度为 window 的时间段大约允许 limit 个请求，具体含义取决于算法。
+    /// 状态只属于这个中间件，与 app_data 中共用的限流器无关
+    pub fn new(algorithm: Algorithm, limit: u32, window: Duration) -> Self {
+        Self {
+            limiter: Some(algorithm.build(limit, window, Arc::new(SystemClock))),
+            message: "Too Many Requests\n",
+            cost: None,
+        }
+    }
+
+    /// 与 app_data 中的 `Arc<dyn RateLimiter>` 共用，方便其他 handler 查询或重置
+    pub fn shared() -> Self {
+        Self {
+            limiter: None,
+            message: "Too Many Requests\n",
+            cost: None,
+        }
+    }
+
+    /// 429 响应的内容
+    pub fn message(mut self, message: &'static str) -> Self {
+        self.message = message;
+        self
+    }
+
+    /// 按请求内容计算消耗，默认每个请求消耗 1。
+    /// 设置后中间件会先读出整个请求体，再原样交给 handler
+    pub fn cost(mut self, cost: Cost) -> Self {
+        self.cost = Some(cost);
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limit: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limit: RateLimit,
+}
+
+/// Retry-After 只能是整秒，向上取整
+fn retry_after_secs(usage: &Usage) -> u64 {
+    usage.retry_after.as_millis().div_ceil(1000) as u64
+}
+
+fn insert_headers(headers: &mut actix_web::http::header::HeaderMap, limit: u32, cost: Option<u32>, usage: &Usage) {
+    // 消耗超过上限的请求等多久都不会通过，不给 Retry-After
+    if cost.is_none_or(|cost| cost <= limit) {
+        headers.insert(RETRY_AFTER, HeaderValue::from(retry_after_secs(usage)));
+    }
+    headers.insert(HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(limit));
+    headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(usage.remaining));
+    if let Some(cost) = cost {
+        headers.insert(HeaderName::from_static("x-ratelimit-cost"), HeaderValue::from(cost));
+    }
+}
+
+#[derive(Serialize)]
+struct Exceeded<'a> {
+    error: &'a str,
+    needed: u32,
+    available: u32,
+    capacity: u32,
+    /// 秒，needed 超过 capacity 时没有
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+}
+
+/// 按消耗计费的 JSON 请求被拒绝时说明需要多少、还剩多少，
+/// 其他请求仍然只返回 message，数量见 X-RateLimit-Cost 和 X-RateLimit-Remaining
+fn exceeded(req: &HttpRequest, message: &str, limit: u32, cost: u32, usage: &Usage) -> HttpResponse {
+    if req.content_type() == "application/json" {
+        return HttpResponse::TooManyRequests().json(Exceeded {
+            error: message.trim_end(),
+            needed: cost,
+            available: usage.remaining,
+            capacity: limit,
+            retry_after: (cost <= limit).then(|| retry_after_secs(usage)),
+        });
+    }
+    HttpResponse::TooManyRequests().body(message.to_string())
+}
+
+/// 读出请求体用于计算消耗，再放回请求中
+async fn weigh(req: &mut ServiceRequest, cost: Cost) -> Result<u32, Error> {
+    let body = req.extract::<Bytes>().await?;
+    let n = cost(req.request(), &body);
+    let stream: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> = Box::pin(stream::once(ready(Ok(body))));
+    req.set_payload(Payload::from(stream));
+    Ok(n)
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limit = self.limit.clone();
+
+        Box::pin(async move {
+            let Some(limiter) = limit
+                .limiter
+                .or_else(|| req.app_data::<web::Data<Arc<dyn RateLimiter>>>().map(|l| l.get_ref().clone()))
+            else {
+                eprintln!("RateLimit::shared() used without a RateLimiter in app_data");
+                return Ok(req.into_response(HttpResponse::InternalServerError().finish()).map_into_right_body());
+            };
+
+            let cost = match limit.cost {
+                Some(cost) => Some(weigh(&mut req, cost).await?),
+                None => None,
+            };
+            let usage = limiter.consume_n(&client_key(req.request()), cost.unwrap_or(1));
+
+            let mut resp = if usage.allowed {
+                if !usage.delay.is_zero() {
+                    sleep(usage.delay).await;
+                }
+                match service.call(req).await {
+                    Ok(resp) => resp.map_into_left_body(),
+                    // 错误照常向外传递，只是换成带限流头的响应
+                    Err(e) => {
+                        let mut resp = e.error_response();
+                        insert_headers(resp.headers_mut(), limiter.limit(), cost, &usage);
+                        return Err(InternalError::from_response(e, resp).into());
+                    }
+                }
+            } else {
+                let body = match cost {
+                    Some(cost) => exceeded(req.request(), limit.message, limiter.limit(), cost, &usage),
+                    None => HttpResponse::TooManyRequests().body(limit.message),
+                };
+                req.into_response(body).map_into_right_body()
+            };
+            insert_headers(resp.headers_mut(), limiter.limit(), cost, &usage);
+            Ok(resp)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use super::*;
+
+    fn request(identity: ClientIdentity, peer: &str) -> TestRequest {
+        TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .app_data(web::Data::new(Arc::new(identity)))
+    }
+
+    fn token(sub: &str, secret: &[u8]) -> String {
+        #[derive(Serialize)]
+        struct Claims<'a> {
+            sub: &'a str,
+            exp: u64,
+        }
+        let claims = Claims { sub, exp: u64::MAX / 2 };
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    #[test]
+    fn only_configured_api_keys_get_their_own_bucket() {
+        let identity = || ClientIdentity::new(["santa".to_string()], []);
+        let req = request(identity(), "10.0.0.1:1234").insert_header(("X-Api-Key", "santa"));
+        assert_eq!(client_key(&req.to_http_request()), "key:santa");
+        let req = request(identity(), "10.0.0.1:1234").insert_header(("X-Api-Key", "grinch"));
+        assert_eq!(client_key(&req.to_http_request()), "ip:10.0.0.1");
+    }
+
+    #[test]
+    fn uses_verified_jwt_subject() {
+        let bearer = |t: String| ("Authorization", format!("Bearer {}", t));
+        let identity = || ClientIdentity::default().jwt_secret("north-pole");
+        let req = request(identity(), "10.0.0.1:1234").insert_header(bearer(token("elf", b"north-pole")));
+        assert_eq!(client_key(&req.to_http_request()), "sub:elf");
+        let req = request(identity(), "10.0.0.1:1234").insert_header(bearer(token("elf", b"forged")));
+        assert_eq!(client_key(&req.to_http_request()), "ip:10.0.0.1");
+    }
+
+    #[test]
+    fn ignores_jwts_without_a_configured_secret() {
+        let bearer = ("Authorization", format!("Bearer {}", token("elf", b"not-a-secret")));
+        let req = request(ClientIdentity::default(), "10.0.0.1:1234").insert_header(bearer);
+        assert_eq!(client_key(&req.to_http_request()), "ip:10.0.0.1");
+    }
+
+    #[test]
+    fn forwarded_headers_need_a_trusted_proxy() {
+        let forwarded = ("X-Forwarded-For", "203.0.113.7");
+        let req = request(ClientIdentity::default(), "10.0.0.1:1234").insert_header(forwarded);
+        assert_eq!(client_key(&req.to_http_request()), "ip:10.0.0.1");
+
+        let proxy = ClientIdentity::new([], ["10.0.0.1".parse().unwrap()]);
+        let req = request(proxy, "10.0.0.1:1234").insert_header(forwarded);
+        assert_eq!(client_key(&req.to_http_request()), "ip:203.0.113.7");
+    }
+
+    const ALGORITHMS: [Algorithm; 5] = [
+        Algorithm::TokenBucket,
+        Algorithm::LeakyBucket,
+        Algorithm::FixedWindow,
+        Algorithm::SlidingLog,
+        Algorithm::SlidingWindow,
+    ];
+
+    /// 用同一串请求时刻（毫秒）驱动限流器，返回每个请求的结果
+    fn replay(algorithm: Algorithm, limit: u32, window_ms: u64, trace: &[u64]) -> Vec<Usage> {
+        let start = Instant::now();
+        let clock = Arc::new(ManualClock::new(start));
+        let limiter = algorithm.build(limit, Duration::from_millis(window_ms), clock.clone());
+        trace
+            .iter()
+            .map(|&at| {
+                clock.set(start + Duration::from_millis(at));
+                limiter.consume_n("trace", 1)
+            })
+            .collect()
+    }
+
+    fn allowed(algorithm: Algorithm, limit: u32, window_ms: u64, trace: &[u64]) -> Vec<bool> {
+        replay(algorithm, limit, window_ms, trace).iter().map(|u| u.allowed).collect()
+    }
+
+    #[test]
+    fn every_algorithm_caps_a_burst_at_limit() {
+        for algorithm in ALGORITHMS {
+            assert_eq!(allowed(algorithm, 3, 1000, &[0; 5]), [true, true, true, false, false], "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn only_fixed_window_doubles_up_at_the_boundary() {
+        let trace = [900, 950, 1000, 1050];
+        for algorithm in ALGORITHMS {
+            let expected = match algorithm {
+                Algorithm::FixedWindow => [true, true, true, true],
+                _ => [true, true, false, false],
+            };
+            assert_eq!(allowed(algorithm, 2, 1000, &trace), expected, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn steady_traffic_at_the_rate() {
+        let trace = [0, 500, 1000, 1500, 2000, 2500];
+        for algorithm in ALGORITHMS {
+            let expected = match algorithm {
+                // 上一个窗口的计数按比例计入，窗口开头的请求会被拒绝
+                Algorithm::SlidingWindow => vec![true, true, false, true, true, false],
+                _ => vec![true; trace.len()],
+            };
+            assert_eq!(allowed(algorithm, 2, 1000, &trace), expected, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn leaky_bucket_delays_instead_of_bursting() {
+        let delays: Vec<_> = replay(Algorithm::LeakyBucket, 4, 1000, &[0; 4])
+            .iter()
+            .map(|u| u.delay.as_millis())
+            .collect();
+        assert_eq!(delays, [0, 250, 500, 750]);
+        for algorithm in ALGORITHMS.into_iter().filter(|a| *a != Algorithm::LeakyBucket) {
+            assert!(replay(algorithm, 4, 1000, &[0; 4]).iter().all(|u| u.delay.is_zero()));
+        }
+    }
+
+    #[test]
+    fn rejected_requests_report_when_to_retry() {
+        for algorithm in ALGORITHMS {
+            let usages = replay(algorithm, 2, 1000, &[0, 0, 0]);
+            let last = usages[2];
+            assert!(!last.allowed);
+            assert_eq!(last.remaining, 0, "{:?}", algorithm);
+            let expected = match algorithm {
+                Algorithm::TokenBucket | Algorithm::LeakyBucket => 500,
+                Algorithm::FixedWindow | Algorithm::SlidingLog => 1000,
+                // 下一个窗口过去一半时，上一个窗口的 2 个请求只算 1 个
+                Algorithm::SlidingWindow => 1500,
+            };
+            assert_eq!(last.retry_after.as_millis(), expected, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn parses_algorithm_names() {
+        assert_eq!("sliding_log".parse(), Ok(Algorithm::SlidingLog));
+        assert!("round_robin".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn sliding_log_handles_huge_limits_and_windows() {
+        let clock = Arc::new(ManualClock::new(Instant::now()));
+        let log = SlidingLog::with_clock(u32::MAX, Duration::MAX, IDLE, clock);
+        let usage = log.consume_n("trace", 3);
+        assert!(usage.allowed);
+        assert_eq!(usage.remaining, u32::MAX - 3);
+    }
+
+    struct Seen(Instant);
+
+    impl Slot for Seen {
+        fn last_seen(&self) -> Instant {
+            self.0
+        }
+    }
+
+    #[test]
+    fn keyed_evicts_idle_slots() {
+        let start = Instant::now();
+        let clock = ManualClock::new(start);
+        let at = |s| start + Duration::from_secs(s);
+        let keyed = Keyed::new(Duration::from_secs(10), start);
+        let exists = |key| keyed.peek(key, &clock, |slot: Option<&mut Seen>, _| slot.is_some());
+        let touch = |key, s| {
+            clock.set(at(s));
+            keyed.with(key, &clock, Seen, |slot, now| slot.0 = now);
+        };
+
+        touch("a", 0);
+        touch("b", 6);
+        // 距离上次清理不到 idle，不清理
+        touch("c", 9);
+        assert!(exists("a"));
+
+        touch("c", 12);
+        assert!(!exists("a"));
+        assert!(exists("b") && exists("c"));
+
+        // 下一次清理要等到 22 秒
+        touch("c", 21);
+        assert!(exists("b"));
+        touch("c", 22);
+        assert!(!exists("b") && exists("c"));
+    }
+
+    async fn fails() -> Result<HttpResponse, Error> {
+        Err(actix_web::error::ErrorBadRequest("bad request"))
+    }
+
+    #[actix_web::test]
+    async fn middleware_sets_headers_on_every_response() {
+        let limit = RateLimit::new(Algorithm::FixedWindow, 2, Duration::from_secs(60)).message("Slow down\n");
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .wrap(limit)
+                .route("/ok", web::get().to(HttpResponse::Ok))
+                .route("/err", web::get().to(fails)),
+        )
+        .await;
+        let call = |path: &str| {
+            TestRequest::get()
+                .uri(path)
+                .peer_addr("10.0.0.1:1234".parse().unwrap())
+                .to_request()
+        };
+        let header = |resp: &ServiceResponse<_>, name: &str| {
+            resp.headers().get(name).map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let resp = actix_web::test::call_service(&app, call("/ok")).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(header(&resp, "x-ratelimit-limit").as_deref(), Some("2"));
+        assert_eq!(header(&resp, "x-ratelimit-remaining").as_deref(), Some("1"));
+
+        let resp = actix_web::test::call_service(&app, call("/err")).await;
+        assert_eq!(resp.status(), 400);
+        assert_eq!(header(&resp, "x-ratelimit-remaining").as_deref(), Some("0"));
+        assert!(header(&resp, "retry-after").is_some());
+
+        let resp = actix_web::test::call_service(&app, call("/ok")).await;
+        assert_eq!(resp.status(), 429);
+        assert_eq!(header(&resp, "x-ratelimit-remaining").as_deref(), Some("0"));
+        assert_eq!(actix_web::test::read_body(resp).await, "Slow down\n");
+    }
+
+    #[actix_web::test]
+    async fn scoped_limits_have_their_own_buckets() {
+        let shared: Arc<dyn RateLimiter> = Algorithm::TokenBucket.build(1, Duration::from_secs(60), Arc::new(SystemClock));
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(shared.clone()))
+                .service(web::scope("/shared").wrap(RateLimit::shared()).route("", web::get().to(HttpResponse::Ok)))
+                .service(
+                    web::scope("/scoped")
+                        .wrap(RateLimit::new(Algorithm::TokenBucket, 3, Duration::from_secs(60)))
+                        .route("", web::get().to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+        let call = |path: &str| {
+            TestRequest::get()
+                .uri(path)
+                .peer_addr("10.0.0.1:1234".parse().unwrap())
+                .to_request()
+        };
+        let remaining = |resp: &ServiceResponse<_>| resp.headers().get("x-ratelimit-remaining").cloned();
+
+        let resp = actix_web::test::call_service(&app, call("/shared")).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(actix_web::test::call_service(&app, call("/shared")).await.status(), 429);
+
+        // 共用的桶已经空了，scope 自己的桶还是满的
+        let resp = actix_web::test::call_service(&app, call("/scoped")).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get("x-ratelimit-limit").unwrap(), "3");
+        assert_eq!(remaining(&resp).unwrap(), "2");
+        for _ in 0..2 {
+            actix_web::test::call_service(&app, call("/scoped")).await;
+        }
+        assert_eq!(actix_web::test::call_service(&app, call("/scoped")).await.status(), 429);
+        assert_eq!(shared.remaining("ip:10.0.0.1"), 0);
+        shared.reset();
+        assert_eq!(actix_web::test::call_service(&app, call("/shared")).await.status(), 200);
+        assert_eq!(actix_web::test::call_service(&app, call("/scoped")).await.status(), 429);
+    }
+
+    #[test]
+    fn works_without_registered_identity() {
+        let req = TestRequest::default()
+            .peer_addr("[::1]:80".parse().unwrap())
+            .insert_header(("X-Api-Key", "santa"));
+        assert_eq!(client_key(&req.to_http_request()), "ip:::1");
+    }
+}
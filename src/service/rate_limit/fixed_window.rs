@@ -0,0 +1,163 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use super::{Clock, Keyed, RateLimiter, Slot, Usage};
+
+struct Window {
+    start: Instant,
+    count: u32,
+    last_seen: Instant,
+}
+
+impl Slot for Window {
+    fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+}
+
+/// 时间按 window 切成固定的段，每段内最多 limit 个请求；
+/// 两段交界处最多可能连续通过 2 * limit 个
+pub struct FixedWindow {
+    limit: u32,
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    /// 窗口从这个时刻开始对齐
+    origin: Instant,
+    windows: Keyed<Window>,
+}
+
+impl FixedWindow {
+    pub fn with_clock(limit: u32, window: Duration, idle: Duration, clock: Arc<dyn Clock>) -> Self {
+        assert!(!window.is_zero(), "window must not be empty");
+        let origin = clock.now();
+        Self {
+            limit,
+            window,
+            windows: Keyed::new(idle.max(window), origin),
+            origin,
+            clock,
+        }
+    }
+
+    /// now 所在窗口的开始时刻
+    fn window_start(&self, now: Instant) -> Instant {
+        let elapsed = now.saturating_duration_since(self.origin).as_nanos();
+        let offset = elapsed - elapsed % self.window.as_nanos();
+        self.origin + Duration::from_nanos(offset as u64)
+    }
+}
+
+impl RateLimiter for FixedWindow {
+    fn consume_n(&self, key: &str, n: u32) -> Usage {
+        let fresh = |now| Window {
+            start: self.window_start(now),
+            count: 0,
+            last_seen: now,
+        };
+        self.windows.with(key, self.clock.as_ref(), fresh, |window, now| {
+            let start = self.window_start(now);
+            // 只前进不后退，否则较早的时刻会清空新窗口的计数
+            if start > window.start {
+                window.start = start;
+                window.count = 0;
+            }
+            let start = window.start;
+            window.last_seen = window.last_seen.max(now);
+
+            let next_window = (start + self.window).saturating_duration_since(now);
+            let available = self.limit.saturating_sub(window.count);
+            if available < n {
+                let retry_after = if n > self.limit { Duration::ZERO } else { next_window };
+                return Usage::rejected(available, retry_after);
+            }
+            window.count += n;
+            let remaining = self.limit - window.count;
+            Usage {
+                allowed: true,
+                remaining,
+                retry_after: if remaining > 0 { Duration::ZERO } else { next_window },
+                delay: Duration::ZERO,
+            }
+        })
+    }
+
+    fn remaining(&self, key: &str) -> u32 {
+        self.windows.peek(key, self.clock.as_ref(), |window, now| match window {
+            Some(window) if window.start >= self.window_start(now) => self.limit.saturating_sub(window.count),
+            _ => self.limit,
+        })
+    }
+
+    fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    fn reset(&self) {
+        self.windows.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ManualClock;
+    use super::*;
+
+    fn windows(limit: u32, window: Duration) -> (FixedWindow, Arc<ManualClock>, Instant) {
+        let start = Instant::now();
+        let clock = Arc::new(ManualClock::new(start));
+        (FixedWindow::with_clock(limit, window, Duration::ZERO, clock.clone()), clock, start)
+    }
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn counts_within_a_window() {
+        let (limiter, clock, start) = windows(3, secs(10));
+        let usage = limiter.consume_n("a", 3);
+        assert!(usage.allowed);
+        assert_eq!((usage.remaining, usage.retry_after), (0, secs(10)));
+
+        clock.set(start + secs(4));
+        let usage = limiter.consume_n("a", 1);
+        assert!(!usage.allowed);
+        assert_eq!(usage.retry_after, secs(6));
+        assert_eq!(limiter.remaining("b"), 3);
+    }
+
+    #[test]
+    fn resets_at_window_boundaries() {
+        let (limiter, clock, start) = windows(3, secs(10));
+        clock.set(start + secs(9));
+        assert!(limiter.consume_n("a", 3).allowed);
+        // 交界处可以连续通过 2 * limit 个
+        clock.set(start + secs(10));
+        assert_eq!(limiter.remaining("a"), 3);
+        assert!(limiter.consume_n("a", 3).allowed);
+
+        // 窗口从创建时对齐，不从第一个请求开始
+        clock.set(start + secs(25));
+        assert_eq!(limiter.consume_n("b", 3).retry_after, secs(5));
+    }
+
+    #[test]
+    fn stale_times_do_not_reset_the_window() {
+        let (limiter, clock, start) = windows(3, secs(10));
+        clock.set(start + secs(12));
+        assert!(limiter.consume_n("a", 3).allowed);
+        // 落在上一个窗口的时刻不清空当前窗口的计数
+        clock.set(start + secs(8));
+        assert_eq!(limiter.remaining("a"), 0);
+        let usage = limiter.consume_n("a", 1);
+        assert!(!usage.allowed);
+        assert_eq!(usage.retry_after, secs(12));
+    }
+
+    #[test]
+    fn oversized_requests_never_pass() {
+        let (limiter, _, _) = windows(3, secs(10));
+        let usage = limiter.consume_n("a", 4);
+        assert!(!usage.allowed);
+        assert_eq!((usage.remaining, usage.retry_after), (3, Duration::ZERO));
+    }
+}
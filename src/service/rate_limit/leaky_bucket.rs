@@ -0,0 +1,154 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use super::{Clock, Keyed, RateLimiter, Slot, Usage};
+
+struct Queue {
+    /// 排队中的请求数，按经过的时间以 rate 的速度流出
+    level: f64,
+    updated: Instant,
+}
+
+impl Slot for Queue {
+    fn last_seen(&self) -> Instant {
+        self.updated
+    }
+}
+
+impl Queue {
+    fn drain(&mut self, now: Instant, rate: f64) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.level = (self.level - elapsed * rate).max(0.0);
+        self.updated = self.updated.max(now);
+    }
+}
+
+/// 漏桶：请求进入长度为 capacity 的队列，按每秒 rate 个的速度放行。
+/// 与令牌桶不同，突发的请求不会立即通过，而是被延迟到均匀的间隔上
+pub struct LeakyBucket {
+    capacity: u32,
+    rate: f64,
+    clock: Arc<dyn Clock>,
+    queues: Keyed<Queue>,
+}
+
+impl LeakyBucket {
+    pub fn with_clock(capacity: u32, rate: f64, idle: Duration, clock: Arc<dyn Clock>) -> Self {
+        assert!(rate > 0.0, "leak rate must be positive");
+        // 队列排空之前不能移除
+        let idle = idle.max(Duration::from_secs_f64(capacity as f64 / rate));
+        Self {
+            capacity,
+            rate,
+            queues: Keyed::new(idle, clock.now()),
+            clock,
+        }
+    }
+}
+
+impl RateLimiter for LeakyBucket {
+    fn consume_n(&self, key: &str, n: u32) -> Usage {
+        let fresh = |now| Queue {
+            level: 0.0,
+            updated: now,
+        };
+        self.queues.with(key, self.clock.as_ref(), fresh, |queue, now| {
+            queue.drain(now, self.rate);
+
+            let capacity = self.capacity as f64;
+            // 队列中要流出多少才能放下 needed 个单位
+            let wait_for = |level: f64, needed: f64| {
+                Duration::from_secs_f64(((level + needed - capacity) / self.rate).max(0.0))
+            };
+            if queue.level + n as f64 > capacity {
+                let available = (capacity - queue.level).max(0.0) as u32;
+                let retry_after = if n > self.capacity {
+                    Duration::ZERO
+                } else {
+                    wait_for(queue.level, n as f64)
+                };
+                return Usage::rejected(available, retry_after);
+            }
+            // 排在已有请求后面
+            let delay = Duration::from_secs_f64(queue.level / self.rate);
+            queue.level += n as f64;
+            Usage {
+                allowed: true,
+                remaining: (capacity - queue.level) as u32,
+                retry_after: wait_for(queue.level, 1.0),
+                delay,
+            }
+        })
+    }
+
+    fn remaining(&self, key: &str) -> u32 {
+        self.queues.peek(key, self.clock.as_ref(), |queue, now| match queue {
+            Some(queue) => {
+                queue.drain(now, self.rate);
+                (self.capacity as f64 - queue.level).max(0.0) as u32
+            }
+            None => self.capacity,
+        })
+    }
+
+    fn limit(&self) -> u32 {
+        self.capacity
+    }
+
+    fn reset(&self) {
+        self.queues.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ManualClock;
+    use super::*;
+
+    fn bucket(capacity: u32, rate: f64) -> (LeakyBucket, Arc<ManualClock>, Instant) {
+        let start = Instant::now();
+        let clock = Arc::new(ManualClock::new(start));
+        (LeakyBucket::with_clock(capacity, rate, Duration::ZERO, clock.clone()), clock, start)
+    }
+
+    fn secs(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn delays_queued_requests() {
+        let (limiter, clock, start) = bucket(2, 1.0);
+        let first = limiter.consume_n("a", 1);
+        assert!(first.allowed);
+        assert_eq!((first.delay, first.remaining, first.retry_after), (Duration::ZERO, 1, Duration::ZERO));
+
+        let second = limiter.consume_n("a", 1);
+        assert!(second.allowed);
+        assert_eq!((second.delay, second.remaining, second.retry_after), (secs(1.0), 0, secs(1.0)));
+
+        let third = limiter.consume_n("a", 1);
+        assert!(!third.allowed);
+        assert_eq!(third.retry_after, secs(1.0));
+
+        clock.set(start + secs(1.5));
+        assert_eq!(limiter.remaining("a"), 1);
+        assert_eq!(limiter.consume_n("a", 1).delay, secs(0.5));
+    }
+
+    #[test]
+    fn drains_at_the_leak_rate() {
+        let (limiter, clock, start) = bucket(4, 2.0);
+        assert!(limiter.consume_n("a", 4).allowed);
+        clock.set(start + secs(1.0));
+        assert_eq!(limiter.remaining("a"), 2);
+        clock.set(start + secs(10.0));
+        assert_eq!(limiter.remaining("a"), 4);
+    }
+
+    #[test]
+    fn oversized_requests_never_pass() {
+        let (limiter, _, _) = bucket(2, 1.0);
+        let usage = limiter.consume_n("a", 3);
+        assert!(!usage.allowed);
+        assert_eq!((usage.remaining, usage.retry_after), (2, Duration::ZERO));
+    }
+}
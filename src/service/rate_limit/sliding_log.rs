@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use super::{Clock, Keyed, RateLimiter, Slot, Usage};
+
+struct Log {
+    /// 最近 window 内通过的请求时刻，从早到晚
+    times: VecDeque<Instant>,
+    last_seen: Instant,
+}
+
+impl Slot for Log {
+    fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+}
+
+impl Log {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while self
+            .times
+            .front()
+            .is_some_and(|&t| now.saturating_duration_since(t) >= window)
+        {
+            self.times.pop_front();
+        }
+    }
+
+    /// 最早的 count 条过期、腾出位置还要多久
+    fn expires(&self, count: usize, now: Instant, window: Duration) -> Duration {
+        match count.checked_sub(1).and_then(|i| self.times.get(i)) {
+            Some(&t) => t
+                .checked_add(window)
+                .map_or(Duration::MAX, |end| end.saturating_duration_since(now)),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// 记录每个请求的时刻，任意长度为 window 的时间段内最多 limit 个请求
+pub struct SlidingLog {
+    limit: u32,
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    logs: Keyed<Log>,
+}
+
+impl SlidingLog {
+    pub fn with_clock(limit: u32, window: Duration, idle: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            limit,
+            window,
+            logs: Keyed::new(idle.max(window), clock.now()),
+            clock,
+        }
+    }
+}
+
+impl RateLimiter for SlidingLog {
+    fn consume_n(&self, key: &str, n: u32) -> Usage {
+        let fresh = |now| Log {
+            times: VecDeque::new(),
+            last_seen: now,
+        };
+        self.logs.with(key, self.clock.as_ref(), fresh, |log, now| {
+            log.expire(now, self.window);
+            log.last_seen = now;
+
+            let available = self.limit.saturating_sub(log.times.len() as u32);
+            // 被拒绝的请求不记录，否则持续请求的客户端永远无法恢复
+            if available < n {
+                let retry_after = if n > self.limit {
+                    Duration::ZERO
+                } else {
+                    log.expires((n - available) as usize, now, self.window)
+                };
+                return Usage::rejected(available, retry_after);
+            }
+            log.times.extend(std::iter::repeat_n(now, n as usize));
+            let remaining = available - n;
+            Usage {
+                allowed: true,
+                remaining,
+                retry_after: if remaining > 0 {
+                    Duration::ZERO
+                } else {
+                    log.expires(1, now, self.window)
+                },
+                delay: Duration::ZERO,
+            }
+        })
+    }
+
+    fn remaining(&self, key: &str) -> u32 {
+        self.logs.peek(key, self.clock.as_ref(), |log, now| match log {
+            Some(log) => {
+                log.expire(now, self.window);
+                self.limit.saturating_sub(log.times.len() as u32)
+            }
+            None => self.limit,
+        })
+    }
+
+    fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    fn reset(&self) {
+        self.logs.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ManualClock;
+    use super::*;
+
+    fn logs(limit: u32, window: Duration) -> (SlidingLog, Arc<ManualClock>, Instant) {
+        let start = Instant::now();
+        let clock = Arc::new(ManualClock::new(start));
+        (SlidingLog::with_clock(limit, window, Duration::ZERO, clock.clone()), clock, start)
+    }
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn waits_for_the_oldest_request_to_expire() {
+        let (limiter, clock, start) = logs(2, secs(10));
+        assert!(limiter.consume_n("a", 1).allowed);
+        clock.set(start + secs(4));
+        let usage = limiter.consume_n("a", 1);
+        assert!(usage.allowed);
+        assert_eq!((usage.remaining, usage.retry_after), (0, secs(6)));
+
+        clock.set(start + secs(6));
+        let usage = limiter.consume_n("a", 1);
+        assert!(!usage.allowed);
+        assert_eq!(usage.retry_after, secs(4));
+
+        clock.set(start + secs(10));
+        assert_eq!(limiter.remaining("a"), 1);
+        // 还要等 4 秒前的那一条过期
+        let usage = limiter.consume_n("a", 2);
+        assert!(!usage.allowed);
+        assert_eq!((usage.remaining, usage.retry_after), (1, secs(4)));
+    }
+
+    #[test]
+    fn rejected_requests_are_not_logged() {
+        let (limiter, clock, start) = logs(1, secs(10));
+        assert!(limiter.consume_n("a", 1).allowed);
+        for s in 1..10 {
+            clock.set(start + secs(s));
+            assert!(!limiter.consume_n("a", 1).allowed);
+        }
+        clock.set(start + secs(10));
+        assert!(limiter.consume_n("a", 1).allowed);
+    }
+
+    #[test]
+    fn oversized_requests_never_pass() {
+        let (limiter, _, _) = logs(2, secs(10));
+        let usage = limiter.consume_n("a", 3);
+        assert!(!usage.allowed);
+        assert_eq!((usage.remaining, usage.retry_after), (2, Duration::ZERO));
+    }
+}
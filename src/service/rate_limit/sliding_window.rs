@@ -0,0 +1,207 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use super::{Clock, Keyed, RateLimiter, Slot, Usage};
+
+struct Counter {
+    /// 当前窗口的开始时刻
+    start: Instant,
+    current: u32,
+    previous: u32,
+    last_seen: Instant,
+}
+
+impl Slot for Counter {
+    fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+}
+
+impl Counter {
+    /// 切换到 start 开始的窗口，隔了不止一个窗口时上一个窗口的计数为 0；
+    /// 比当前窗口早的 start 不处理，否则会清空新窗口的计数
+    fn advance(&mut self, start: Instant, window: Duration) {
+        if start <= self.start {
+            return;
+        }
+        self.previous = if start - self.start == window { self.current } else { 0 };
+        self.current = 0;
+        self.start = start;
+    }
+
+    /// 上一个窗口的计数按仍在滑动窗口内的比例计入
+    fn estimate(&self, progress: f64) -> f64 {
+        self.previous as f64 * (1.0 - progress) + self.current as f64
+    }
+}
+
+/// 只保存当前和上一个固定窗口的计数，用两者加权估算滑动窗口内的请求数
+pub struct SlidingWindow {
+    limit: u32,
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    origin: Instant,
+    counters: Keyed<Counter>,
+}
+
+impl SlidingWindow {
+    pub fn with_clock(limit: u32, window: Duration, idle: Duration, clock: Arc<dyn Clock>) -> Self {
+        assert!(!window.is_zero(), "window must not be empty");
+        let origin = clock.now();
+        Self {
+            limit,
+            window,
+            // 上一个窗口的计数还要用到，至少保留两个窗口
+            counters: Keyed::new(idle.max(window * 2), origin),
+            origin,
+            clock,
+        }
+    }
+
+    /// now 所在窗口的开始时刻，以及已经过去的比例
+    fn position(&self, now: Instant) -> (Instant, f64) {
+        let elapsed = now.saturating_duration_since(self.origin).as_nanos();
+        let into = elapsed % self.window.as_nanos();
+        let start = self.origin + Duration::from_nanos((elapsed - into) as u64);
+        (start, into as f64 / self.window.as_nanos() as f64)
+    }
+
+    /// 估算值降到能再通过 n 个单位还要多久，n 超过 limit 时返回 0
+    fn retry_after(&self, counter: &Counter, progress: f64, n: u32) -> Duration {
+        if n > self.limit {
+            return Duration::ZERO;
+        }
+        let room = (self.limit - n) as f64;
+        let wait = if counter.current + n <= self.limit {
+            // 当前窗口内等上一个窗口的权重降下来
+            let target = 1.0 - (room - counter.current as f64) / counter.previous as f64;
+            (target - progress).max(0.0)
+        } else {
+            // 等到下一个窗口，当前计数成为上一个窗口的计数
+            let target = 1.0 - room / counter.current as f64;
+            1.0 - progress + target
+        };
+        self.window.mul_f64(wait)
+    }
+}
+
+impl RateLimiter for SlidingWindow {
+    fn consume_n(&self, key: &str, n: u32) -> Usage {
+        let fresh = |now| Counter {
+            start: self.position(now).0,
+            current: 0,
+            previous: 0,
+            last_seen: now,
+        };
+        self.counters.with(key, self.clock.as_ref(), fresh, |counter, now| {
+            let (start, progress) = self.position(now);
+            counter.advance(start, self.window);
+            // 时刻落在已经过去的窗口里时，按当前窗口刚开始估算
+            let progress = if start < counter.start { 0.0 } else { progress };
+            counter.last_seen = counter.last_seen.max(now);
+
+            let available = (self.limit as f64 - counter.estimate(progress)).max(0.0);
+            if available < n as f64 {
+                return Usage::rejected(available as u32, self.retry_after(counter, progress, n));
+            }
+            counter.current += n;
+            let remaining = (self.limit as f64 - counter.estimate(progress)).max(0.0) as u32;
+            Usage {
+                allowed: true,
+                remaining,
+                retry_after: if remaining > 0 {
+                    Duration::ZERO
+                } else {
+                    self.retry_after(counter, progress, 1)
+                },
+                delay: Duration::ZERO,
+            }
+        })
+    }
+
+    fn remaining(&self, key: &str) -> u32 {
+        self.counters.peek(key, self.clock.as_ref(), |counter, now| match counter {
+            Some(counter) => {
+                let (start, progress) = self.position(now);
+                counter.advance(start, self.window);
+                let progress = if start < counter.start { 0.0 } else { progress };
+                (self.limit as f64 - counter.estimate(progress)).max(0.0) as u32
+            }
+            None => self.limit,
+        })
+    }
+
+    fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    fn reset(&self) {
+        self.counters.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ManualClock;
+    use super::*;
+
+    fn counters(limit: u32, window: Duration) -> (SlidingWindow, Arc<ManualClock>, Instant) {
+        let start = Instant::now();
+        let clock = Arc::new(ManualClock::new(start));
+        (SlidingWindow::with_clock(limit, window, Duration::ZERO, clock.clone()), clock, start)
+    }
+
+    fn secs(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn weights_the_previous_window() {
+        let (limiter, clock, start) = counters(4, secs(10.0));
+        let usage = limiter.consume_n("a", 4);
+        assert!(usage.allowed);
+        // 下一个窗口过去 1/4 时估算值降到 3
+        assert_eq!((usage.remaining, usage.retry_after), (0, secs(12.5)));
+
+        clock.set(start + secs(10.0));
+        let usage = limiter.consume_n("a", 1);
+        assert!(!usage.allowed);
+        assert_eq!(usage.retry_after, secs(2.5));
+
+        clock.set(start + secs(15.0));
+        assert_eq!(limiter.remaining("a"), 2);
+        assert!(limiter.consume_n("a", 2).allowed);
+        assert!(!limiter.consume_n("a", 1).allowed);
+    }
+
+    #[test]
+    fn forgets_counts_older_than_one_window() {
+        let (limiter, clock, start) = counters(4, secs(10.0));
+        assert!(limiter.consume_n("a", 4).allowed);
+        clock.set(start + secs(25.0));
+        assert_eq!(limiter.remaining("a"), 4);
+        assert!(limiter.consume_n("a", 4).allowed);
+    }
+
+    #[test]
+    fn stale_times_do_not_reset_the_window() {
+        let (limiter, clock, start) = counters(4, secs(10.0));
+        assert!(limiter.consume_n("a", 2).allowed);
+        clock.set(start + secs(12.0));
+        assert!(limiter.consume_n("a", 2).allowed);
+        // 落在上一个窗口的时刻不把当前计数挪进上一个窗口
+        clock.set(start + secs(8.0));
+        assert_eq!(limiter.remaining("a"), 0);
+        assert!(!limiter.consume_n("a", 1).allowed);
+
+        clock.set(start + secs(15.0));
+        assert_eq!(limiter.remaining("a"), 1);
+    }
+
+    #[test]
+    fn oversized_requests_never_pass() {
+        let (limiter, _, _) = counters(4, secs(10.0));
+        let usage = limiter.consume_n("a", 5);
+        assert!(!usage.allowed);
+        assert_eq!((usage.remaining, usage.retry_after), (4, Duration::ZERO));
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use super::{Clock, Keyed, RateLimiter, Slot, Usage};
+
+struct Bucket {
+    /// 上次更新时的令牌数，可以是小数
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Slot for Bucket {
+    fn last_seen(&self) -> Instant {
+        self.updated
+    }
+}
+
+impl Bucket {
+    /// 按经过的时间补充令牌，不超过 burst
+    fn refill(&mut self, now: Instant, rate: f64, burst: u32) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+        self.updated = self.updated.max(now);
+    }
+}
+
+/// 按客户端分开的令牌桶，令牌数在每次访问时根据经过的时间计算
+pub struct TokenBuckets {
+    /// 桶的容量，即最多能连续发出的请求数
+    burst: u32,
+    /// 每秒补充的令牌数
+    rate: f64,
+    clock: Arc<dyn Clock>,
+    buckets: Keyed<Bucket>,
+}
+
+impl TokenBuckets {
+    pub fn with_clock(burst: u32, rate: f64, idle: Duration, clock: Arc<dyn Clock>) -> Self {
+        assert!(rate > 0.0, "refill rate must be positive");
+        // 空闲时间短于补满所需的时间时，移除会让客户端提前拿到满桶
+        let idle = idle.max(Duration::from_secs_f64(burst as f64 / rate));
+        Self {
+            burst,
+            rate,
+            buckets: Keyed::new(idle, clock.now()),
+            clock,
+        }
+    }
+}
+
+impl RateLimiter for TokenBuckets {
+    fn consume_n(&self, key: &str, n: u32) -> Usage {
+        let fresh = |now| Bucket {
+            tokens: self.burst as f64,
+            updated: now,
+        };
+        self.buckets.with(key, self.clock.as_ref(), fresh, |bucket, now| {
+            bucket.refill(now, self.rate, self.burst);
+
+            // 攒够 needed 个令牌还要多久
+            let wait_for = |tokens: f64, needed: f64| {
+                Duration::from_secs_f64(((needed - tokens) / self.rate).max(0.0))
+            };
+            if bucket.tokens < n as f64 {
+                let retry_after = if n > self.burst {
+                    Duration::ZERO
+                } else {
+                    wait_for(bucket.tokens, n as f64)
+                };
+                return Usage::rejected(bucket.tokens as u32, retry_after);
+            }
+            bucket.tokens -= n as f64;
+            Usage {
+                allowed: true,
+                remaining: bucket.tokens as u32,
+                retry_after: wait_for(bucket.tokens, 1.0),
+                delay: Duration::ZERO,
+            }
+        })
+    }
+
+    fn remaining(&self, key: &str) -> u32 {
+        self.buckets.peek(key, self.clock.as_ref(), |bucket, now| match bucket {
+            Some(bucket) => {
+                bucket.refill(now, self.rate, self.burst);
+                bucket.tokens as u32
+            }
+            None => self.burst,
+        })
+    }
+
+    fn limit(&self) -> u32 {
+        self.burst
+    }
+
+    /// 所有客户端恢复满桶
+    fn reset(&self) {
+        self.buckets.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ManualClock;
+    use super::*;
+
+    fn buckets(burst: u32, rate: f64) -> (TokenBuckets, Arc<ManualClock>, Instant) {
+        let start = Instant::now();
+        let clock = Arc::new(ManualClock::new(start));
+        (TokenBuckets::with_clock(burst, rate, Duration::ZERO, clock.clone()), clock, start)
+    }
+
+    fn secs(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn refills_fractional_tokens() {
+        let (limiter, clock, start) = buckets(2, 0.5);
+        assert!(limiter.consume_n("a", 2).allowed);
+
+        clock.set(start + secs(1.0));
+        let usage = limiter.consume_n("a", 1);
+        assert!(!usage.allowed);
+        assert_eq!(usage.remaining, 0);
+        // 已经攒了半个令牌，再等 1 秒
+        assert_eq!(usage.retry_after, secs(1.0));
+
+        clock.set(start + secs(2.0));
+        assert!(limiter.consume_n("a", 1).allowed);
+        clock.set(start + secs(3.0));
+        assert_eq!(limiter.remaining("a"), 0);
+        clock.set(start + secs(4.0));
+        assert_eq!(limiter.remaining("a"), 1);
+    }
+
+    #[test]
+    fn never_exceeds_burst() {
+        let (limiter, clock, start) = buckets(3, 1.0);
+        assert!(limiter.consume_n("a", 1).allowed);
+        clock.set(start + Duration::from_secs(3600));
+        assert_eq!(limiter.remaining("a"), 3);
+        assert!(limiter.consume_n("a", 3).allowed);
+        assert!(!limiter.consume_n("a", 1).allowed);
+        // 其他客户端不受影响
+        assert_eq!(limiter.consume_n("b", 3).remaining, 0);
+    }
+
+    #[test]
+    fn retry_after_counts_missing_tokens() {
+        let (limiter, _, _) = buckets(4, 2.0);
+        let usage = limiter.consume_n("a", 4);
+        assert!(usage.allowed);
+        assert_eq!(usage.retry_after, secs(0.5));
+
+        let usage = limiter.consume_n("a", 3);
+        assert!(!usage.allowed);
+        assert_eq!(usage.retry_after, secs(1.5));
+
+        // 超过容量的请求等多久都不会通过
+        let usage = limiter.consume_n("b", 5);
+        assert!(!usage.allowed);
+        assert_eq!((usage.remaining, usage.retry_after), (4, Duration::ZERO));
+    }
+
+    #[test]
+    fn keeps_buckets_until_they_would_be_full() {
+        let (limiter, clock, start) = buckets(10, 1.0);
+        assert!(limiter.consume_n("a", 10).allowed);
+        // idle 为 0，但补满要 10 秒，之前的清理不能移除 a
+        clock.set(start + Duration::from_secs(5));
+        limiter.consume_n("b", 1);
+        assert_eq!(limiter.remaining("a"), 5);
+
+        clock.set(start + Duration::from_secs(20));
+        limiter.consume_n("b", 1);
+        assert_eq!(limiter.remaining("a"), 10);
+        limiter.reset();
+        assert_eq!(limiter.remaining("b"), 10);
+    }
+}